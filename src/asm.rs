@@ -0,0 +1,679 @@
+//! An assembler for the classic syntax written by `disassemble`.
+//!
+//! Besides one mnemonic for every `Instruction` it understands:
+//!
+//! * `name:` labels, on their own line or in front of an instruction
+//! * `name equ 0x20` constants
+//! * `db 1, 2, 3` bytes and `dw 0x1234` big endian words
+//! * `sprite` ... `end` blocks with one row of `#` and `.` per line, 8 or 16 wide
+//! * `include "other.asm"`, relative to the including file
+//!
+//! Numbers can be decimal, hex with `0x` or `$`, or binary with `0b`, and anywhere a number is
+//! expected a sum or difference of numbers and names works too, e.g. `table + 2`. Comments
+//! start with `;`.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::Instruction;
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 32;
+
+/// A problem with the source, and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+struct SourceLine {
+    file: Rc<str>,
+    number: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.number,
+            message: message.into(),
+        }
+    }
+}
+
+enum Item {
+    Instruction(String, Vec<Operand>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Sprite(Vec<u8>),
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(String),
+}
+
+struct Statement {
+    line: usize,
+    item: Item,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(usize),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(String),
+    Value(String),
+}
+
+impl Operand {
+    fn parse(s: &str) -> Operand {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            upper => {
+                if upper.len() == 2 && upper.starts_with('V') {
+                    if let Ok(reg) = usize::from_str_radix(&upper[1..], 16) {
+                        return Operand::V(reg);
+                    }
+                }
+                match upper.strip_prefix("LONG ") {
+                    Some(_) => Operand::Long(s[5..].trim().to_string()),
+                    None => Operand::Value(s.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Assembles source held in memory. Included files are found relative to the working
+/// directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = vec![];
+    read_lines(
+        "<input>".into(),
+        source,
+        Path::new("."),
+        &mut vec![],
+        &mut lines,
+    )?;
+    Assembler::new(lines).run()
+}
+
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut lines = vec![];
+    include(path, None, &mut vec![], &mut lines)?;
+    Assembler::new(lines).run()
+}
+
+fn include(
+    path: &Path,
+    from: Option<&SourceLine>,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AsmError> {
+    let name = path.display().to_string();
+    let error = |message: String| match from {
+        Some(line) => line.error(message),
+        None => AsmError {
+            file: name.clone(),
+            line: 0,
+            message,
+        },
+    };
+
+    if stack.len() == MAX_INCLUDE_DEPTH || stack.iter().any(|p| p == path) {
+        return Err(error(format!("{} includes itself", name)));
+    }
+    let source = fs::read_to_string(path)
+        .map_err(|err| error(format!("Could not read {}: {}", name, err)))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    stack.push(path.to_path_buf());
+    read_lines(name.as_str().into(), &source, dir, stack, lines)?;
+    stack.pop();
+
+    Ok(())
+}
+
+/// Splits source into lines, with any included files spliced in.
+fn read_lines(
+    file: Rc<str>,
+    source: &str,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AsmError> {
+    for (index, text) in source.lines().enumerate() {
+        let line = SourceLine {
+            file: file.clone(),
+            number: index + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+
+        let (word, rest) = split_word(&line.text);
+        if word.eq_ignore_ascii_case("include") {
+            let name = rest
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+                .ok_or_else(|| line.error("Expected a quoted file name after include"))?;
+            include(&dir.join(name), Some(&line), stack, lines)?;
+        } else {
+            lines.push(line);
+        }
+    }
+
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..index],
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Splits off the first word of a line.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+struct Assembler {
+    lines: Vec<SourceLine>,
+    symbols: HashMap<String, Symbol>,
+    statements: Vec<Statement>,
+}
+
+impl Assembler {
+    fn new(lines: Vec<SourceLine>) -> Self {
+        Assembler {
+            lines,
+            symbols: HashMap::new(),
+            statements: vec![],
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        self.collect()?;
+
+        let mut rom = vec![];
+        for statement in &self.statements {
+            let line = &self.lines[statement.line];
+            let bytes = self
+                .encode(&statement.item)
+                .map_err(|message| line.error(message))?;
+            rom.extend(bytes);
+        }
+
+        Ok(rom)
+    }
+
+    /// The first pass: works out where every label is, without evaluating anything.
+    fn collect(&mut self) -> Result<(), AsmError> {
+        let mut address = PROGRAM_START;
+        let mut in_sprite = false;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            let mut text = line.text.as_str();
+
+            if in_sprite {
+                if text.eq_ignore_ascii_case("end") {
+                    in_sprite = false;
+                } else if !text.is_empty() {
+                    let row = sprite_row(text).map_err(|message| line.error(message))?;
+                    address += row.len();
+                    self.statements.push(Statement {
+                        line: index,
+                        item: Item::Sprite(row),
+                    });
+                }
+                continue;
+            }
+
+            if let Some(colon) = text.find(':') {
+                let label = &text[..colon];
+                if is_name(label) {
+                    define(
+                        &mut self.symbols,
+                        line,
+                        label,
+                        Symbol::Label(address as u16),
+                    )?;
+                    text = text[colon + 1..].trim();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            let (second, value) = split_word(rest);
+            if second.eq_ignore_ascii_case("equ") {
+                if !is_name(word) {
+                    return Err(line.error(format!("Invalid constant name: {}", word)));
+                }
+                define(
+                    &mut self.symbols,
+                    line,
+                    word,
+                    Symbol::Constant(value.to_string()),
+                )?;
+                continue;
+            }
+
+            let operands: Vec<String> = if rest.is_empty() {
+                vec![]
+            } else {
+                rest.split(',').map(|s| s.trim().to_string()).collect()
+            };
+            let item = match word.to_ascii_lowercase().as_str() {
+                "sprite" if rest.is_empty() => {
+                    in_sprite = true;
+                    continue;
+                }
+                "db" => Item::Bytes(operands),
+                "dw" => Item::Words(operands),
+                _ => Item::Instruction(
+                    word.to_ascii_uppercase(),
+                    operands.iter().map(|s| Operand::parse(s)).collect(),
+                ),
+            };
+
+            address += match &item {
+                Item::Instruction(_, operands) if operands.iter().any(is_long) => 4,
+                Item::Instruction(..) => 2,
+                Item::Bytes(values) => values.len(),
+                Item::Words(values) => values.len() * 2,
+                Item::Sprite(row) => row.len(),
+            };
+            if address > MEMORY_SIZE {
+                return Err(line.error("Program doesn't fit in memory"));
+            }
+
+            self.statements.push(Statement { line: index, item });
+        }
+
+        if in_sprite {
+            let last = self
+                .lines
+                .last()
+                .expect("a sprite block has at least one line");
+            return Err(last.error("Sprite block is missing its end"));
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        self.evaluate_at_depth(expression, 0)
+    }
+
+    fn evaluate_at_depth(&self, expression: &str, depth: usize) -> Result<i64, String> {
+        if depth == MAX_CONSTANT_DEPTH {
+            return Err(format!("Constant refers to itself: {}", expression));
+        }
+
+        let mut total = 0;
+        let mut rest = expression.trim();
+        loop {
+            let mut sign = 1;
+            while let Some(after) = rest.strip_prefix('-').or_else(|| rest.strip_prefix('+')) {
+                if rest.starts_with('-') {
+                    sign = -sign;
+                }
+                rest = after.trim_start();
+            }
+
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            if term.is_empty() {
+                return Err(format!("Expected a value: {}", expression));
+            }
+            total += sign * self.term(term, depth)?;
+
+            if end == rest.len() {
+                break;
+            }
+            // Leave the operator in place to be read as the next term's sign
+            rest = &rest[end..];
+        }
+
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, depth: usize) -> Result<i64, String> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+
+        match self.symbols.get(term) {
+            Some(Symbol::Label(address)) => Ok(*address as i64),
+            Some(Symbol::Constant(expression)) => self.evaluate_at_depth(expression, depth + 1),
+            None if is_name(term) => Err(format!("Unknown name: {}", term)),
+            None => Err(format!("Invalid value: {}", term)),
+        }
+    }
+
+    fn ranged(&self, expression: &str, min: i64, max: i64, what: &str) -> Result<i64, String> {
+        let value = self.evaluate(expression)?;
+        if value < min || value > max {
+            return Err(format!("{} is out of range for {}", value, what));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, expression: &str) -> Result<u8, String> {
+        Ok(self.ranged(expression, -128, 0xFF, "a byte")? as u8)
+    }
+
+    fn nibble(&self, expression: &str) -> Result<u8, String> {
+        Ok(self.ranged(expression, 0, 0xF, "a nibble")? as u8)
+    }
+
+    fn address(&self, expression: &str) -> Result<u16, String> {
+        Ok(self.ranged(expression, 0, 0xFFF, "an address")? as u16)
+    }
+
+    fn long_address(&self, expression: &str) -> Result<u16, String> {
+        Ok(self.ranged(expression, 0, 0xFFFF, "a long address")? as u16)
+    }
+
+    fn encode(&self, item: &Item) -> Result<Vec<u8>, String> {
+        match item {
+            Item::Instruction(mnemonic, operands) => {
+                Ok(self.instruction(mnemonic, operands)?.encode())
+            }
+            Item::Bytes(values) => values.iter().map(|value| self.byte(value)).collect(),
+            Item::Words(values) => {
+                let mut bytes = vec![];
+                for value in values {
+                    let word = self.ranged(value, -0x8000, 0xFFFF, "a word")? as u16;
+                    bytes.extend(word.to_be_bytes().iter());
+                }
+                Ok(bytes)
+            }
+            Item::Sprite(row) => Ok(row.clone()),
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+        use Operand::*;
+
+        let instr = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("SYS", [Value(a)]) => Instruction::SysCall(self.address(a)?),
+            ("SCD", [Value(n)]) => Instruction::ScrollDown(self.nibble(n)?),
+            ("SCU", [Value(n)]) => Instruction::ScrollUp(self.nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("JP", [Value(a)]) => Instruction::Jump(self.address(a)?),
+            ("JP", [V(0), Value(a)]) => Instruction::JumpOffset(self.address(a)?),
+            ("CALL", [Value(a)]) => Instruction::Call(self.address(a)?),
+            ("SE", [V(x), V(y)]) => Instruction::RegEqReg(*x, *y),
+            ("SE", [V(x), Value(b)]) => Instruction::RegEq(*x, self.byte(b)?),
+            ("SNE", [V(x), V(y)]) => Instruction::RegNeqReg(*x, *y),
+            ("SNE", [V(x), Value(b)]) => Instruction::RegNeq(*x, self.byte(b)?),
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange(*x, *y),
+            ("LD", [V(x), V(y)]) => Instruction::RegSetReg(*x, *y),
+            ("LD", [V(x), Value(b)]) => Instruction::SetReg(*x, self.byte(b)?),
+            ("LD", [V(x), DelayTimer]) => Instruction::GetDelay(*x),
+            ("LD", [V(x), Key]) => Instruction::WaitKey(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::RegLoad(*x),
+            ("LD", [V(x), Flags]) => Instruction::LoadFlags(*x),
+            ("LD", [I, Value(a)]) => Instruction::SetAddress(self.address(a)?),
+            ("LD", [I, Long(a)]) => Instruction::LongSetAddress(self.long_address(a)?),
+            ("LD", [DelayTimer, V(x)]) => Instruction::SetDelay(*x),
+            ("LD", [SoundTimer, V(x)]) => Instruction::SetSound(*x),
+            ("LD", [Font, V(x)]) => Instruction::SpriteAddress(*x),
+            ("LD", [BigFont, V(x)]) => Instruction::BigSpriteAddress(*x),
+            ("LD", [Bcd, V(x)]) => Instruction::BCD(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::RegDump(*x),
+            ("LD", [Flags, V(x)]) => Instruction::SaveFlags(*x),
+            ("ADD", [V(x), V(y)]) => Instruction::Add(*x, *y),
+            ("ADD", [V(x), Value(b)]) => Instruction::IncReg(*x, self.byte(b)?),
+            ("ADD", [I, V(x)]) => Instruction::IncAddress(*x),
+            ("OR", [V(x), V(y)]) => Instruction::Or(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::And(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::XOr(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::RevSub(*x, *y),
+            // Shifting a register by itself does the same thing whichever quirk is in use
+            ("SHR", [V(x)]) => Instruction::ShiftR(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftR(*x, *y),
+            ("SHL", [V(x)]) => Instruction::ShiftL(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftL(*x, *y),
+            ("RND", [V(x), Value(b)]) => Instruction::Random(*x, self.byte(b)?),
+            ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw(*x, *y, self.nibble(n)?),
+            ("SKP", [V(x)]) => Instruction::KeyEq(*x),
+            ("SKNP", [V(x)]) => Instruction::KeyNeq(*x),
+            ("PLANE", [Value(n)]) => Instruction::SelectPlanes(self.nibble(n)?),
+            ("AUDIO", []) => Instruction::LoadAudioPattern,
+            ("PITCH", [V(x)]) => Instruction::SetPitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err(format!("Invalid operands for {}", mnemonic))
+            }
+            _ => return Err(format!("Unknown instruction: {}", mnemonic)),
+        };
+
+        Ok(instr)
+    }
+}
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn is_long(operand: &Operand) -> bool {
+    matches!(operand, Operand::Long(_))
+}
+
+fn define(
+    symbols: &mut HashMap<String, Symbol>,
+    line: &SourceLine,
+    name: &str,
+    symbol: Symbol,
+) -> Result<(), AsmError> {
+    if symbols.contains_key(name) {
+        return Err(line.error(format!("{} is already defined", name)));
+    }
+    symbols.insert(name.to_string(), symbol);
+    Ok(())
+}
+
+/// Turns a row like `..####..` into bytes.
+fn sprite_row(text: &str) -> Result<Vec<u8>, String> {
+    let row: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if row.len() != 8 && row.len() != 16 {
+        return Err(format!("Sprite rows must be 8 or 16 pixels wide: {}", text));
+    }
+
+    row.as_bytes()
+        .chunks(8)
+        .map(|chunk| {
+            chunk.iter().try_fold(0u8, |byte, &c| match c {
+                b'#' => Ok(byte << 1 | 1),
+                b'.' => Ok(byte << 1),
+                _ => Err(format!("Sprite rows can only contain # and .: {}", text)),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble, Syntax};
+
+    fn error_at(source: &str) -> (usize, String) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.message)
+    }
+
+    #[test]
+    fn disassembly_assembles_back_to_the_same_bytes() {
+        let body = [
+            Instruction::Clear,
+            Instruction::SysCall(0x123),
+            Instruction::ScrollDown(3),
+            Instruction::ScrollUp(4),
+            Instruction::ScrollRight,
+            Instruction::ScrollLeft,
+            Instruction::LowRes,
+            Instruction::HighRes,
+            Instruction::RegEq(1, 0x22),
+            Instruction::RegNeq(2, 0x33),
+            Instruction::RegEqReg(3, 4),
+            Instruction::RegNeqReg(5, 6),
+            Instruction::SaveRange(1, 7),
+            Instruction::LoadRange(8, 2),
+            Instruction::SetReg(0xA, 0xFF),
+            Instruction::IncReg(0xB, 0x80),
+            Instruction::RegSetReg(0xC, 0xD),
+            Instruction::Or(1, 2),
+            Instruction::And(3, 4),
+            Instruction::XOr(5, 6),
+            Instruction::Add(7, 8),
+            Instruction::Sub(9, 0xA),
+            Instruction::ShiftR(0xB, 0xB),
+            Instruction::ShiftR(0xB, 0xC),
+            Instruction::RevSub(0xD, 0xE),
+            Instruction::ShiftL(0xF, 0xF),
+            Instruction::ShiftL(0xF, 0),
+            Instruction::SetAddress(0x345),
+            Instruction::Draw(1, 2, 0),
+            Instruction::LongSetAddress(0xABCD),
+            Instruction::Random(3, 0x0F),
+            Instruction::KeyEq(4),
+            Instruction::KeyNeq(5),
+            Instruction::GetDelay(6),
+            Instruction::WaitKey(7),
+            Instruction::SetDelay(8),
+            Instruction::SetSound(9),
+            Instruction::IncAddress(0xA),
+            Instruction::SpriteAddress(0xB),
+            Instruction::BigSpriteAddress(0xC),
+            Instruction::BCD(0xD),
+            Instruction::RegDump(0xE),
+            Instruction::RegLoad(0xF),
+            Instruction::SaveFlags(1),
+            Instruction::LoadFlags(2),
+            Instruction::SelectPlanes(3),
+            Instruction::LoadAudioPattern,
+            Instruction::SetPitch(4),
+        ];
+        let body: Vec<u8> = body.iter().flat_map(|instr| instr.encode()).collect();
+
+        // The instructions that end a run of code each get a subroutine of their own
+        let subs = (PROGRAM_START + body.len() + 4 * 2) as u16;
+        let tail = [
+            Instruction::Call(subs),
+            Instruction::Call(subs + 2),
+            Instruction::Call(subs + 4),
+            Instruction::Jump(PROGRAM_START as u16),
+            Instruction::Return,
+            Instruction::Exit,
+            Instruction::JumpOffset(0x456),
+        ];
+        let mut rom = body;
+        rom.extend(tail.iter().flat_map(|instr| instr.encode()));
+
+        let source = disassemble(&rom, Syntax::Classic);
+        assert!(!source.contains("db"), "{}", source);
+        assert_eq!(assemble(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn reports_the_line_of_unknown_instructions() {
+        assert_eq!(
+            error_at("CLS\n\n    FOO V1\n"),
+            (3, "Unknown instruction: FOO".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_operands() {
+        assert_eq!(
+            error_at("CLS\nADD V1, I\n"),
+            (2, "Invalid operands for ADD".to_string())
+        );
+        assert_eq!(
+            error_at("start:\n    LD V1, 0x100\n"),
+            (2, "256 is out of range for a byte".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_undefined_labels() {
+        assert_eq!(
+            error_at("CLS\nCALL sub\nJP nowhere\nsub:\nRET\n"),
+            (3, "Unknown name: nowhere".to_string())
+        );
+    }
+}
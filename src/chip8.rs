@@ -0,0 +1,152 @@
+use std::io;
+
+use crate::debugger::{Debugger, StopReason};
+use crate::hardware::{AudioSink, DisplayTarget, Keyboard, NullAudio, Quirks, CPU};
+use crate::trace::{TraceRecord, TraceSink};
+use crate::EmulatorError;
+
+/// A complete CHIP-8 machine: the `CPU` together with the keypad it reads from, and a
+/// debugger that can pause it.
+pub struct Chip8 {
+    cpu: CPU,
+    keyboard: Keyboard,
+    debugger: Debugger,
+    tracer: Option<Box<dyn TraceSink>>,
+}
+
+impl Chip8 {
+    pub fn new(audio: Box<dyn AudioSink>, quirks: Quirks) -> Self {
+        Chip8 {
+            cpu: CPU::new(audio, quirks),
+            keyboard: Keyboard::new(),
+            debugger: Debugger::new(),
+            tracer: None,
+        }
+    }
+
+    /// A machine with no sound output, for tests and tools.
+    pub fn headless(quirks: Quirks) -> Self {
+        Chip8::new(Box::new(NullAudio), quirks)
+    }
+
+    /// See `CPU::set_seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.set_seed(seed);
+    }
+
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        self.cpu.load_rom(data)
+    }
+
+    /// Runs one instruction, unless the debugger has paused the machine or stops it here.
+    ///
+    /// A fault also pauses the debugger, leaving the program counter on the faulting
+    /// instruction. Nothing is drawn; `Scheduler` runs whole frames and presents them.
+    pub fn step(&mut self) -> Result<(), EmulatorError> {
+        if self.debugger.is_paused() || self.debugger.should_stop_before(&self.cpu) {
+            return Ok(());
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            if !self.cpu.is_halted() {
+                tracer.record(&TraceRecord::capture(&self.cpu));
+            }
+        }
+
+        self.cpu.set_track_accesses(self.debugger.watches_memory());
+        if let Err(err) = self.cpu.step(&self.keyboard) {
+            self.debugger.stop(StopReason::Fault(err.clone()));
+            return Err(err);
+        }
+
+        self.debugger.check_after(&self.cpu);
+
+        Ok(())
+    }
+
+    /// Ends an emulated frame: see `CPU::tick_frame`. The keypad's presses and releases
+    /// are forgotten too, and watchpoints on DT and ST see the timers change.
+    pub fn tick_frame(&mut self) {
+        self.debugger.before_tick(&self.cpu);
+        self.cpu.tick_frame();
+        self.keyboard.end_frame();
+        self.debugger.check_after_tick(&self.cpu);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.debugger.is_paused()
+    }
+
+    pub fn pause(&mut self) {
+        self.debugger.pause();
+    }
+
+    pub fn resume(&mut self) {
+        self.debugger.resume(&self.cpu);
+    }
+
+    /// Lets exactly one instruction run on the next `step`.
+    pub fn step_into(&mut self) {
+        self.debugger.step_into(&self.cpu);
+    }
+
+    /// Like `step_into`, but runs a whole subroutine if the next instruction is a `Call`.
+    pub fn step_over(&mut self) {
+        self.debugger.step_over(&self.cpu);
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self) {
+        self.debugger.step_out(&self.cpu);
+    }
+
+    pub fn run_to(&mut self, address: u16) {
+        self.debugger.run_to(address, &self.cpu);
+    }
+
+    /// Starts recording every instruction run to `tracer`, replacing any previous one.
+    pub fn set_tracer(&mut self, tracer: Box<dyn TraceSink>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn flush_trace(&mut self) -> io::Result<()> {
+        match &mut self.tracer {
+            Some(tracer) => tracer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Presents the current screen without running anything, e.g. after loading a state.
+    pub fn draw(&self, display: &mut dyn DisplayTarget) {
+        display.draw(self.cpu.framebuffer());
+    }
+
+    /// See `CPU::save_state`. The keypad is host input, so isn't part of the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        self.cpu.load_state(data)
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keyboard {
+        &mut self.keyboard
+    }
+}
@@ -0,0 +1,816 @@
+use std::{fmt, str::FromStr};
+
+use crate::hardware::{AccessKind, CPU};
+use crate::{EmulatorError, Instruction};
+
+/// A value a breakpoint condition can test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Register::V(reg) => cpu.registers()[reg] as u16,
+            Register::I => cpu.address_register(),
+            Register::DelayTimer => cpu.delay_timer() as u16,
+            Register::SoundTimer => cpu.sound_timer() as u16,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "DT" => Ok(Register::DelayTimer),
+            "ST" => Ok(Register::SoundTimer),
+            name if name.len() == 2 && name.starts_with('V') => {
+                usize::from_str_radix(&name[1..], 16)
+                    .map(Register::V)
+                    .map_err(|_| format!("Unknown register: {}", s))
+            }
+            _ => Err(format!("Unknown register: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(reg) => write!(f, "V{:X}", reg),
+            Register::I => write!(f, "I"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    fn holds(&self, left: u16, right: u16) -> bool {
+        match self {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+}
+
+/// A test on a register, such as `V3 == 5` or `I >= 0x300`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.comparison.holds(self.register.read(cpu), self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Longer symbols first, so `<=` isn't read as `<`
+        let symbols = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+
+        for &(symbol, comparison) in symbols.iter() {
+            if let Some(index) = s.find(symbol) {
+                let register = s[..index].trim().parse()?;
+                let value = parse_number(s[index + symbol.len()..].trim())?;
+                return Ok(Condition {
+                    register,
+                    comparison,
+                    value,
+                });
+            }
+        }
+
+        Err(format!("Expected a comparison such as V0 == 5: {}", s))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:#X}",
+            self.register,
+            self.comparison.symbol(),
+            self.value
+        )
+    }
+}
+
+/// Parses a decimal number, or hex with a `0x` or `$` prefix.
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+
+    result.map_err(|_| format!("Invalid number: {}", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Condition>,
+}
+
+/// Which kinds of memory access a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    fn matches(&self, kind: AccessKind) -> bool {
+        match self {
+            WatchAccess::Read => kind == AccessKind::Read,
+            WatchAccess::Write => kind == AccessKind::Write,
+            WatchAccess::ReadWrite => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// Stops after an instruction reads or writes any address from `start` to `end` inclusive.
+    /// Fetching instructions doesn't count.
+    Memory {
+        start: u16,
+        end: u16,
+        access: WatchAccess,
+    },
+    /// Stops after an instruction changes the register, if the condition holds afterwards.
+    /// DT and ST are also checked after the timers tick at the end of each frame.
+    Register {
+        register: Register,
+        condition: Option<Condition>,
+    },
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { start, end, access } => {
+                let access = match access {
+                    WatchAccess::Read => "read",
+                    WatchAccess::Write => "write",
+                    WatchAccess::ReadWrite => "access",
+                };
+                if start == end {
+                    write!(f, "{} {:#05X}", access, start)
+                } else {
+                    write!(f, "{} {:#05X}-{:#05X}", access, start, end)
+                }
+            }
+            Watchpoint::Register {
+                register,
+                condition,
+            } => match condition {
+                Some(condition) => write!(f, "{} if {}", register, condition),
+                None => write!(f, "{}", register),
+            },
+        }
+    }
+}
+
+/// What a watchpoint saw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchHit {
+    Read {
+        address: u16,
+        value: u8,
+    },
+    Write {
+        address: u16,
+        value: u8,
+    },
+    Register {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+/// Why the debugger paused the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Paused,
+    Breakpoint(u16),
+    Step,
+    Fault(EmulatorError),
+    /// A watchpoint was hit by the instruction at `pc`.
+    Watchpoint {
+        pc: u16,
+        hit: WatchHit,
+    },
+    /// A watchpoint on DT or ST was hit by the timers ticking at the end of a frame.
+    TimerWatchpoint {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    StepInto,
+    StepOver { depth: usize, return_address: u16 },
+    StepOut { depth: usize },
+    RunTo(u16),
+}
+
+/// Breakpoints, watchpoints and run control for a `Chip8`.
+///
+/// The machine asks the debugger before and after every instruction whether it should stop.
+/// Once paused, `Chip8::step` does nothing until execution is resumed or stepped.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    mode: Mode,
+    paused: bool,
+    stop: Option<StopReason>,
+    resume_from: Option<u16>,
+    // The program counter and watched register values before the current instruction
+    before: (u16, Vec<u16>),
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: vec![],
+            watchpoints: vec![],
+            mode: Mode::Run,
+            paused: false,
+            stop: None,
+            resume_from: None,
+            before: (0, vec![]),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Adds a breakpoint, replacing any existing one at the same address.
+    pub fn add_breakpoint(&mut self, address: u16, condition: Option<Condition>) {
+        self.remove_breakpoint(address);
+        self.breakpoints.push(Breakpoint { address, condition });
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.address != address);
+        self.breakpoints.len() != before
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Removes the watchpoint at `index` in `watchpoints()`.
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        if index < self.watchpoints.len() {
+            self.watchpoints.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Whether the `CPU` needs to record its memory accesses for us.
+    pub(crate) fn watches_memory(&self) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|wp| matches!(wp, Watchpoint::Memory { .. }))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The reason for the most recent stop, if it hasn't been collected yet.
+    pub fn take_stop(&mut self) -> Option<StopReason> {
+        self.stop.take()
+    }
+
+    pub fn pause(&mut self) {
+        self.stop(StopReason::Paused);
+    }
+
+    pub fn resume(&mut self, cpu: &CPU) {
+        self.continue_with(Mode::Run, cpu);
+    }
+
+    /// Runs a single instruction.
+    pub fn step_into(&mut self, cpu: &CPU) {
+        self.continue_with(Mode::StepInto, cpu);
+    }
+
+    /// Runs a single instruction, treating a `Call` and everything it runs as one step.
+    pub fn step_over(&mut self, cpu: &CPU) {
+        let mode = match cpu.next_instruction() {
+            Some(Instruction::Call(_)) => Mode::StepOver {
+                depth: cpu.stack().len(),
+                return_address: cpu.program_counter().wrapping_add(2),
+            },
+            _ => Mode::StepInto,
+        };
+        self.continue_with(mode, cpu);
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self, cpu: &CPU) {
+        let depth = cpu.stack().len();
+        if depth == 0 {
+            return;
+        }
+        self.continue_with(Mode::StepOut { depth }, cpu);
+    }
+
+    pub fn run_to(&mut self, address: u16, cpu: &CPU) {
+        self.continue_with(Mode::RunTo(address), cpu);
+    }
+
+    fn continue_with(&mut self, mode: Mode, cpu: &CPU) {
+        self.mode = mode;
+        self.paused = false;
+        // Don't stop straight away on the breakpoint we were paused at
+        self.resume_from = Some(cpu.program_counter());
+    }
+
+    pub(crate) fn stop(&mut self, reason: StopReason) {
+        self.mode = Mode::Run;
+        self.paused = true;
+        self.stop = Some(reason);
+    }
+
+    /// Checked before each instruction runs. Returns `true` if the machine should stop.
+    pub(crate) fn should_stop_before(&mut self, cpu: &CPU) -> bool {
+        let pc = cpu.program_counter();
+        let resuming = self.resume_from.take() == Some(pc);
+
+        if !resuming {
+            let hit = self.breakpoints.iter().any(|bp| {
+                bp.address == pc && bp.condition.map_or(true, |condition| condition.holds(cpu))
+            });
+            if hit {
+                self.stop(StopReason::Breakpoint(pc));
+                return true;
+            }
+        }
+
+        let arrived = match self.mode {
+            Mode::RunTo(address) => pc == address,
+            Mode::StepOver {
+                depth,
+                return_address,
+            } => pc == return_address && cpu.stack().len() == depth,
+            _ => false,
+        };
+        if arrived {
+            self.stop(StopReason::Step);
+            return true;
+        }
+
+        self.before = (pc, self.register_values(cpu));
+        false
+    }
+
+    /// The value of each watched register, with a placeholder for memory watchpoints.
+    fn register_values(&self, cpu: &CPU) -> Vec<u16> {
+        self.watchpoints
+            .iter()
+            .map(|wp| match wp {
+                Watchpoint::Register { register, .. } => register.read(cpu),
+                Watchpoint::Memory { .. } => 0,
+            })
+            .collect()
+    }
+
+    /// Checked before the timers tick, which changes DT and ST without an instruction.
+    pub(crate) fn before_tick(&mut self, cpu: &CPU) {
+        self.before = (cpu.program_counter(), self.register_values(cpu));
+    }
+
+    /// Checked after the timers tick.
+    pub(crate) fn check_after_tick(&mut self, cpu: &CPU) {
+        if self.paused {
+            return;
+        }
+        let hit =
+            self.watchpoints
+                .iter()
+                .zip(self.before.1.iter())
+                .find_map(|(wp, &old)| match *wp {
+                    Watchpoint::Register {
+                        register,
+                        condition,
+                    } => register_hit(register, condition, old, cpu),
+                    Watchpoint::Memory { .. } => None,
+                });
+        if let Some(WatchHit::Register { register, old, new }) = hit {
+            self.stop(StopReason::TimerWatchpoint { register, old, new });
+        }
+    }
+
+    /// Checked after each instruction runs.
+    pub(crate) fn check_after(&mut self, cpu: &CPU) {
+        if let Some(hit) = self.watch_hit(cpu) {
+            let pc = self.before.0;
+            self.stop(StopReason::Watchpoint { pc, hit });
+            return;
+        }
+
+        let done = match self.mode {
+            Mode::StepInto => true,
+            Mode::StepOut { depth } => cpu.stack().len() < depth,
+            _ => false,
+        };
+        if done {
+            self.stop(StopReason::Step);
+        }
+    }
+
+    fn watch_hit(&self, cpu: &CPU) -> Option<WatchHit> {
+        for (wp, &old) in self.watchpoints.iter().zip(self.before.1.iter()) {
+            match *wp {
+                Watchpoint::Memory { start, end, access } => {
+                    let range = start as usize..=end as usize;
+                    let seen = cpu.memory_accesses().iter().find(|memory_access| {
+                        range.contains(&memory_access.address) && access.matches(memory_access.kind)
+                    });
+                    if let Some(memory_access) = seen {
+                        let (address, value) = (memory_access.address as u16, memory_access.value);
+                        return Some(match memory_access.kind {
+                            AccessKind::Read => WatchHit::Read { address, value },
+                            AccessKind::Write => WatchHit::Write { address, value },
+                        });
+                    }
+                }
+                Watchpoint::Register {
+                    register,
+                    condition,
+                } => {
+                    let hit = register_hit(register, condition, old, cpu);
+                    if hit.is_some() {
+                        return hit;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A register watchpoint's hit, if the register has changed from `old` and the condition
+/// holds.
+fn register_hit(
+    register: Register,
+    condition: Option<Condition>,
+    old: u16,
+    cpu: &CPU,
+) -> Option<WatchHit> {
+    let new = register.read(cpu);
+    if new != old && condition.map_or(true, |condition| condition.holds(cpu)) {
+        Some(WatchHit::Register { register, old, new })
+    } else {
+        None
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{Quirks, RecordingDisplay};
+    use crate::{Chip8, Scheduler};
+
+    /// Counts V0 up to 5, calling a subroutine that calls another each time round.
+    const ROM: &[u8] = &[
+        0x60, 0x00, // 200: LD V0, 0
+        0x70, 0x01, // 202: ADD V0, 1
+        0x22, 0x10, // 204: CALL 0x210
+        0x30, 0x05, // 206: SE V0, 5
+        0x12, 0x02, // 208: JP 0x202
+        0x12, 0x0A, // 20A: JP 0x20A
+        0x00, 0x00, 0x00, 0x00, //
+        0x22, 0x20, // 210: CALL 0x220
+        0x71, 0x10, // 212: ADD V1, 0x10
+        0x00, 0xEE, // 214: RET
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x72, 0x01, // 220: ADD V2, 1
+        0x00, 0xEE, // 222: RET
+    ];
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    /// Steps until the debugger pauses, returning why it did.
+    fn run(chip8: &mut Chip8) -> Option<StopReason> {
+        for _ in 0..1000 {
+            if chip8.is_paused() {
+                break;
+            }
+            chip8.step().unwrap();
+        }
+        chip8.debugger_mut().take_stop()
+    }
+
+    fn pc(chip8: &Chip8) -> u16 {
+        chip8.cpu().program_counter()
+    }
+
+    fn depth(chip8: &Chip8) -> usize {
+        chip8.cpu().stack().len()
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut chip8 = machine(ROM);
+        chip8.debugger_mut().add_breakpoint(0x212, None);
+
+        assert_eq!(run(&mut chip8), Some(StopReason::Breakpoint(0x212)));
+        assert_eq!((chip8.cpu().registers()[0], depth(&chip8)), (1, 1));
+
+        // Resuming runs the instruction it stopped at, then stops next time round
+        chip8.resume();
+        assert_eq!(run(&mut chip8), Some(StopReason::Breakpoint(0x212)));
+        assert_eq!(chip8.cpu().registers()[0], 2);
+
+        assert!(chip8.debugger_mut().remove_breakpoint(0x212));
+        chip8.debugger_mut().add_breakpoint(0x20A, None);
+        chip8.resume();
+        assert_eq!(run(&mut chip8), Some(StopReason::Breakpoint(0x20A)));
+        assert_eq!(chip8.cpu().registers()[1], 0x50);
+    }
+
+    #[test]
+    fn only_stops_when_the_condition_holds() {
+        let mut chip8 = machine(ROM);
+        let condition = "V0 == 3".parse().unwrap();
+        chip8.debugger_mut().add_breakpoint(0x206, Some(condition));
+
+        assert_eq!(run(&mut chip8), Some(StopReason::Breakpoint(0x206)));
+        assert_eq!(chip8.cpu().registers()[0], 3);
+        assert_eq!(chip8.cpu().registers()[2], 3);
+    }
+
+    #[test]
+    fn steps_over_calls() {
+        let mut chip8 = machine(ROM);
+        chip8.run_to(0x204);
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!(pc(&chip8), 0x204);
+
+        chip8.step_over();
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!((pc(&chip8), depth(&chip8)), (0x206, 0));
+        assert_eq!(chip8.cpu().registers()[1..3], [0x10, 1]);
+
+        // Anything but a call is a single step
+        chip8.step_over();
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!(pc(&chip8), 0x208);
+    }
+
+    #[test]
+    fn steps_over_a_call_made_inside_a_subroutine() {
+        let mut chip8 = machine(ROM);
+        chip8.run_to(0x210);
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!(depth(&chip8), 1);
+
+        chip8.step_over();
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!((pc(&chip8), depth(&chip8)), (0x212, 1));
+        assert_eq!(chip8.cpu().registers()[2], 1);
+    }
+
+    #[test]
+    fn steps_out_of_subroutines() {
+        let mut chip8 = machine(ROM);
+        chip8.run_to(0x220);
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!(depth(&chip8), 2);
+
+        chip8.step_out();
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!((pc(&chip8), depth(&chip8)), (0x212, 1));
+
+        chip8.step_out();
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!((pc(&chip8), depth(&chip8)), (0x206, 0));
+    }
+
+    #[test]
+    fn stepping_out_of_the_top_level_does_nothing() {
+        let mut chip8 = machine(ROM);
+        chip8.pause();
+        assert_eq!(run(&mut chip8), Some(StopReason::Paused));
+
+        chip8.step_out();
+        assert!(chip8.is_paused());
+        assert_eq!(run(&mut chip8), None);
+        assert_eq!(pc(&chip8), 0x200);
+    }
+
+    #[test]
+    fn runs_to_an_address() {
+        let mut chip8 = machine(ROM);
+        chip8.run_to(0x20A);
+        assert_eq!(run(&mut chip8), Some(StopReason::Step));
+        assert_eq!(pc(&chip8), 0x20A);
+        assert_eq!(chip8.cpu().registers()[0], 5);
+
+        // A breakpoint on the way stops it first
+        let mut chip8 = machine(ROM);
+        chip8.debugger_mut().add_breakpoint(0x214, None);
+        chip8.run_to(0x20A);
+        assert_eq!(run(&mut chip8), Some(StopReason::Breakpoint(0x214)));
+    }
+
+    /// Stores 7 at 0x300, setting V3 to the value it already has, then to 1 and 5.
+    const WATCHED_ROM: &[u8] = &[
+        0xA3, 0x00, // 200: LD I, 0x300
+        0x63, 0x00, // 202: LD V3, 0
+        0x60, 0x07, // 204: LD V0, 7
+        0xF0, 0x55, // 206: LD [I], V0
+        0x63, 0x01, // 208: LD V3, 1
+        0x63, 0x05, // 20A: LD V3, 5
+        0x12, 0x0C, // 20C: JP 0x20C
+    ];
+
+    #[test]
+    fn stops_after_a_watched_address_is_written() {
+        let mut chip8 = machine(WATCHED_ROM);
+        chip8.debugger_mut().add_watchpoint(Watchpoint::Memory {
+            start: 0x300,
+            end: 0x300,
+            access: WatchAccess::Write,
+        });
+
+        let hit = WatchHit::Write {
+            address: 0x300,
+            value: 7,
+        };
+        assert_eq!(
+            run(&mut chip8),
+            Some(StopReason::Watchpoint { pc: 0x206, hit })
+        );
+        assert_eq!(pc(&chip8), 0x208);
+    }
+
+    #[test]
+    fn stops_after_a_watched_register_changes() {
+        let mut chip8 = machine(WATCHED_ROM);
+        chip8.debugger_mut().add_watchpoint(Watchpoint::Register {
+            register: Register::I,
+            condition: None,
+        });
+
+        let hit = WatchHit::Register {
+            register: Register::I,
+            old: 0,
+            new: 0x300,
+        };
+        assert_eq!(
+            run(&mut chip8),
+            Some(StopReason::Watchpoint { pc: 0x200, hit })
+        );
+    }
+
+    #[test]
+    fn ignores_registers_set_to_the_value_they_had() {
+        let mut chip8 = machine(WATCHED_ROM);
+        chip8.debugger_mut().add_watchpoint(Watchpoint::Register {
+            register: Register::V(3),
+            condition: None,
+        });
+
+        let hit = WatchHit::Register {
+            register: Register::V(3),
+            old: 0,
+            new: 1,
+        };
+        assert_eq!(
+            run(&mut chip8),
+            Some(StopReason::Watchpoint { pc: 0x208, hit })
+        );
+    }
+
+    #[test]
+    fn only_stops_on_a_watched_register_when_the_condition_holds() {
+        let mut chip8 = machine(WATCHED_ROM);
+        chip8.debugger_mut().add_watchpoint(Watchpoint::Register {
+            register: Register::V(3),
+            condition: Some("V3 == 5".parse().unwrap()),
+        });
+
+        let hit = WatchHit::Register {
+            register: Register::V(3),
+            old: 1,
+            new: 5,
+        };
+        assert_eq!(
+            run(&mut chip8),
+            Some(StopReason::Watchpoint { pc: 0x20A, hit })
+        );
+    }
+
+    #[test]
+    fn watches_the_timers_run_down() {
+        // LD V0, 3; LD DT, V0; then loops forever
+        let mut chip8 = machine(&[0x60, 0x03, 0xF0, 0x15, 0x12, 0x04]);
+        chip8.debugger_mut().add_watchpoint(Watchpoint::Register {
+            register: Register::DelayTimer,
+            condition: Some("DT == 0".parse().unwrap()),
+        });
+        let scheduler = Scheduler::new(10);
+        let mut display = RecordingDisplay::new();
+
+        for _ in 0..2 {
+            scheduler.run_frame(&mut chip8, &mut display).unwrap();
+            assert!(!chip8.is_paused());
+        }
+        scheduler.run_frame(&mut chip8, &mut display).unwrap();
+        assert_eq!(
+            chip8.debugger_mut().take_stop(),
+            Some(StopReason::TimerWatchpoint {
+                register: Register::DelayTimer,
+                old: 1,
+                new: 0
+            })
+        );
+        assert_eq!(pc(&chip8), 0x204);
+    }
+}
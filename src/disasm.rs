@@ -0,0 +1,510 @@
+//! Turns ROM bytes back into assembly source.
+//!
+//! Code is found by following the program from its entry point through jumps, calls and
+//! skips, so anything only reached through a `BNNN` jump table or computed address shows up
+//! as data. Bytes that `I` points at when a `Draw` runs are laid out one sprite row per line.
+//! Every byte of the ROM appears in the output, so assembling it gives back the same ROM.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Instruction;
+
+const PROGRAM_START: usize = 0x200;
+const DATA_BYTES_PER_LINE: usize = 8;
+const COMMENT_COLUMN: usize = 28;
+
+/// The assembly dialect to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Upper case mnemonics in the style of Cowgod's reference, e.g. `LD V1, 0x20`.
+    Classic,
+    /// Octo, e.g. `v1 := 0x20`.
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Syntax::Classic),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+
+    fn comment(&self) -> &'static str {
+        match self {
+            Syntax::Classic => ";",
+            Syntax::Octo => "#",
+        }
+    }
+}
+
+// Ordered so that the most telling use of an address names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Sprite,
+    Label,
+    Sub,
+}
+
+impl LabelKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            LabelKind::Data => "data",
+            LabelKind::Sprite => "sprite",
+            LabelKind::Label => "label",
+            LabelKind::Sub => "sub",
+        }
+    }
+}
+
+enum Line {
+    Code(Instruction, usize),
+    Sprite(u8),
+    Data(Vec<u8>),
+}
+
+struct Analysis {
+    code: BTreeMap<usize, (Instruction, usize)>,
+    sprites: BTreeSet<usize>,
+    targets: BTreeMap<usize, LabelKind>,
+}
+
+impl Analysis {
+    fn target(&mut self, address: u16, kind: LabelKind) {
+        let entry = self.targets.entry(address as usize).or_insert(kind);
+        *entry = (*entry).max(kind);
+    }
+}
+
+/// Decodes the instruction at `address`, with its length in bytes.
+fn fetch(rom: &[u8], address: usize) -> Option<(Instruction, usize)> {
+    let offset = address.checked_sub(PROGRAM_START)?;
+    let word = |offset: usize| -> Option<u16> {
+        let bytes = rom.get(offset..offset + 2)?;
+        Some(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    };
+
+    let opcode = word(offset)?;
+    if Instruction::is_long(opcode) {
+        Some((Instruction::decode_long(opcode, word(offset + 2)?)?, 4))
+    } else {
+        Some((Instruction::decode(opcode)?, 2))
+    }
+}
+
+fn analyse(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        code: BTreeMap::new(),
+        sprites: BTreeSet::new(),
+        targets: BTreeMap::new(),
+    };
+
+    let mut pending = vec![PROGRAM_START];
+    while let Some(start) = pending.pop() {
+        let mut address = start;
+        // Where I points, as long as we can tell from this straight run of code
+        let mut sprite_address = None;
+
+        while !analysis.code.contains_key(&address) {
+            let (instr, length) = match fetch(rom, address) {
+                Some(decoded) => decoded,
+                None => break,
+            };
+            analysis.code.insert(address, (instr, length));
+            let next = address + length;
+
+            match instr {
+                Instruction::Jump(target) | Instruction::JumpOffset(target) => {
+                    analysis.target(target, LabelKind::Label);
+                    pending.push(target as usize);
+                    break;
+                }
+                Instruction::Call(target) => {
+                    analysis.target(target, LabelKind::Sub);
+                    pending.push(target as usize);
+                }
+                Instruction::Return | Instruction::Exit => break,
+                Instruction::RegEq(..)
+                | Instruction::RegNeq(..)
+                | Instruction::RegEqReg(..)
+                | Instruction::RegNeqReg(..)
+                | Instruction::KeyEq(_)
+                | Instruction::KeyNeq(_) => {
+                    let skipped = fetch(rom, next).map_or(2, |(_, length)| length);
+                    pending.push(next + skipped);
+                }
+                Instruction::SetAddress(target) | Instruction::LongSetAddress(target) => {
+                    analysis.target(target, LabelKind::Data);
+                    sprite_address = Some(target as usize);
+                }
+                Instruction::Draw(_, _, height) => {
+                    if let Some(sprite) = sprite_address {
+                        let size = if height == 0 { 32 } else { height as usize };
+                        analysis.sprites.extend(sprite..sprite + size);
+                        analysis.target(sprite as u16, LabelKind::Sprite);
+                    }
+                }
+                Instruction::IncAddress(_)
+                | Instruction::SpriteAddress(_)
+                | Instruction::BigSpriteAddress(_)
+                | Instruction::RegDump(_)
+                | Instruction::RegLoad(_) => sprite_address = None,
+                _ => (),
+            }
+
+            address = next;
+        }
+    }
+
+    analysis
+}
+
+fn layout(rom: &[u8], analysis: &Analysis) -> Vec<(usize, Line)> {
+    let end = PROGRAM_START + rom.len();
+    let mut lines = vec![];
+
+    let mut address = PROGRAM_START;
+    while address < end {
+        let line = if let Some(&(instr, length)) = analysis.code.get(&address) {
+            Line::Code(instr, length)
+        } else if analysis.sprites.contains(&address) {
+            Line::Sprite(rom[address - PROGRAM_START])
+        } else {
+            let mut bytes = vec![rom[address - PROGRAM_START]];
+            let mut next = address + 1;
+            while next < end
+                && bytes.len() < DATA_BYTES_PER_LINE
+                && !analysis.code.contains_key(&next)
+                && !analysis.sprites.contains(&next)
+                && !analysis.targets.contains_key(&next)
+            {
+                bytes.push(rom[next - PROGRAM_START]);
+                next += 1;
+            }
+            Line::Data(bytes)
+        };
+
+        let length = match &line {
+            Line::Code(_, length) => *length,
+            Line::Sprite(_) => 1,
+            Line::Data(bytes) => bytes.len(),
+        };
+        lines.push((address, line));
+        address += length;
+    }
+
+    lines
+}
+
+/// Disassembles a ROM that is loaded at 0x200.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let analysis = analyse(rom);
+    let lines = layout(rom, &analysis);
+
+    // Only addresses that start a line can be given a label
+    let starts: BTreeSet<usize> = lines.iter().map(|(address, _)| *address).collect();
+    let labels: BTreeMap<usize, String> = analysis
+        .targets
+        .iter()
+        .filter(|(address, _)| starts.contains(address))
+        .map(|(&address, kind)| (address, format!("{}_{:03X}", kind.prefix(), address)))
+        .collect();
+    let name = |address: u16| labels.get(&(address as usize)).cloned();
+
+    let comment = syntax.comment();
+    let mut out = format!(
+        "{} Disassembly of {} bytes, loaded at {:#05X}\n",
+        comment,
+        rom.len(),
+        PROGRAM_START
+    );
+
+    for (address, line) in lines {
+        if let Some(label) = labels.get(&address) {
+            match syntax {
+                Syntax::Classic => out.push_str(&format!("\n{}:\n", label)),
+                Syntax::Octo => out.push_str(&format!("\n: {}\n", label)),
+            }
+        }
+
+        let offset = address - PROGRAM_START;
+        let (text, note) = match line {
+            Line::Code(instr, length) => {
+                let bytes = &rom[offset..offset + length];
+                match format_instruction(&instr, syntax, &name) {
+                    Some(text) => (text, hex(bytes)),
+                    None => (data_directive(bytes, syntax), format!("{:?}", instr)),
+                }
+            }
+            Line::Sprite(byte) => {
+                let pixels: String = (0..8)
+                    .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect();
+                let text = match syntax {
+                    Syntax::Classic => format!("db {:#010b}", byte),
+                    Syntax::Octo => format!("{:#010b}", byte),
+                };
+                (text, pixels)
+            }
+            Line::Data(bytes) => (data_directive(&bytes, syntax), String::new()),
+        };
+
+        let text = format!("    {}", text);
+        let line = format!(
+            "{:width$} {} {:03X}: {}",
+            text,
+            comment,
+            address,
+            note,
+            width = COMMENT_COLUMN - 1
+        );
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn data_directive(bytes: &[u8], syntax: Syntax) -> String {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+    match syntax {
+        Syntax::Classic => format!("db {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+/// Writes a single instruction, naming addresses with `name` where it has a label for them.
+///
+/// Returns `None` for instructions the syntax has no mnemonic for, which have to be written
+/// as data instead.
+fn format_instruction(
+    instr: &Instruction,
+    syntax: Syntax,
+    name: &dyn Fn(u16) -> Option<String>,
+) -> Option<String> {
+    let address = |address: u16| name(address).unwrap_or_else(|| format!("{:#05X}", address));
+    let long_address = |address: u16| name(address).unwrap_or_else(|| format!("{:#06X}", address));
+
+    match syntax {
+        Syntax::Classic => Some(instr.format_classic(&address, &long_address)),
+        Syntax::Octo => octo(instr, name, &address, &long_address),
+    }
+}
+
+fn octo(
+    instr: &Instruction,
+    name: &dyn Fn(u16) -> Option<String>,
+    address: &dyn Fn(u16) -> String,
+    long_address: &dyn Fn(u16) -> String,
+) -> Option<String> {
+    let text = match *instr {
+        // Octo can only call machine code routines by writing the opcode out as data
+        Instruction::SysCall(_) => return None,
+        Instruction::Clear => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollDown(rows) => format!("scroll-down {}", rows),
+        Instruction::ScrollUp(rows) => format!("scroll-up {}", rows),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LowRes => "lores".to_string(),
+        Instruction::HighRes => "hires".to_string(),
+        Instruction::Jump(addr) => format!("jump {}", address(addr)),
+        // A bare label is a call, but a bare number would be data
+        Instruction::Call(addr) => match name(addr) {
+            Some(label) => label,
+            None => format!(":call {:#05X}", addr),
+        },
+        // Octo's conditionals run the next instruction when they hold, the opposite way
+        // round to CHIP-8's skips
+        Instruction::RegEq(x, value) => format!("if v{:x} != {:#04X} then", x, value),
+        Instruction::RegNeq(x, value) => format!("if v{:x} == {:#04X} then", x, value),
+        Instruction::RegEqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::RegNeqReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::KeyEq(x) => format!("if v{:x} -key then", x),
+        Instruction::KeyNeq(x) => format!("if v{:x} key then", x),
+        Instruction::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Instruction::SetReg(x, value) => format!("v{:x} := {:#04X}", x, value),
+        Instruction::IncReg(x, value) => format!("v{:x} += {:#04X}", x, value),
+        Instruction::RegSetReg(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::XOr(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::Add(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShiftR(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::RevSub(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShiftL(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SetAddress(addr) => format!("i := {}", address(addr)),
+        Instruction::LongSetAddress(addr) => format!("i := long {}", long_address(addr)),
+        Instruction::JumpOffset(addr) => format!("jump0 {}", address(addr)),
+        Instruction::Random(x, value) => format!("v{:x} := random {:#04X}", x, value),
+        Instruction::Draw(x, y, height) => format!("sprite v{:x} v{:x} {}", x, y, height),
+        Instruction::GetDelay(x) => format!("v{:x} := delay", x),
+        Instruction::WaitKey(x) => format!("v{:x} := key", x),
+        Instruction::SetDelay(x) => format!("delay := v{:x}", x),
+        Instruction::SetSound(x) => format!("buzzer := v{:x}", x),
+        Instruction::IncAddress(x) => format!("i += v{:x}", x),
+        Instruction::SpriteAddress(x) => format!("i := hex v{:x}", x),
+        Instruction::BigSpriteAddress(x) => format!("i := bighex v{:x}", x),
+        Instruction::RegDump(x) => format!("save v{:x}", x),
+        Instruction::RegLoad(x) => format!("load v{:x}", x),
+        Instruction::BCD(x) => format!("bcd v{:x}", x),
+        Instruction::SaveFlags(x) => format!("saveflags v{:x}", x),
+        Instruction::LoadFlags(x) => format!("loadflags v{:x}", x),
+        Instruction::SelectPlanes(planes) => format!("plane {}", planes),
+        Instruction::LoadAudioPattern => "audio".to_string(),
+        Instruction::SetPitch(x) => format!("pitch := v{:x}", x),
+    };
+
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One instruction from each opcode family, then a table of data and an odd byte.
+    const ROM: &[u8] = &[
+        0x00, 0xE0, // 200
+        0x01, 0x23, // 202
+        0x00, 0xC3, // 204
+        0x00, 0xFB, // 206
+        0x00, 0xFF, // 208
+        0x6A, 0x12, // 20A
+        0x7B, 0x01, // 20C
+        0x81, 0x24, // 20E
+        0x81, 0x26, // 210
+        0x31, 0x05, // 212
+        0x91, 0x20, // 214
+        0xE1, 0x9E, // 216
+        0xC3, 0x0F, // 218
+        0xF3, 0x0A, // 21A
+        0xF5, 0x33, // 21C
+        0x51, 0x22, // 21E
+        0xF2, 0x01, // 220
+        0xF0, 0x00, 0x12, 0x34, // 222
+        0x22, 0x2E, // 226
+        0xB3, 0x00, // 228
+        0xAB, 0xCD, 0xEF, 0x01, // 22A
+        0x00, 0xEE, // 22E
+        0x7F, // 230
+    ];
+
+    /// The code of every line, without the comments.
+    fn code(rom: &[u8], syntax: Syntax) -> Vec<String> {
+        let comment = syntax.comment();
+        disassemble(rom, syntax)
+            .lines()
+            .skip(1)
+            .map(|line| line.split(comment).next().unwrap().trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn classic_syntax() {
+        assert_eq!(
+            code(ROM, Syntax::Classic),
+            [
+                "CLS",
+                "SYS 0x123",
+                "SCD 3",
+                "SCR",
+                "HIGH",
+                "LD VA, 0x12",
+                "ADD VB, 0x01",
+                "ADD V1, V2",
+                "SHR V1, V2",
+                "SE V1, 0x05",
+                "SNE V1, V2",
+                "SKP V1",
+                "RND V3, 0x0F",
+                "LD V3, K",
+                "LD B, V5",
+                "SAVE V1, V2",
+                "PLANE 2",
+                "LD I, LONG 0x1234",
+                "CALL sub_22E",
+                "JP V0, 0x300",
+                "db 0xAB, 0xCD, 0xEF, 0x01",
+                "sub_22E:",
+                "RET",
+                "db 0x7F",
+            ]
+        );
+    }
+
+    #[test]
+    fn octo_syntax() {
+        assert_eq!(
+            code(ROM, Syntax::Octo),
+            [
+                "clear",
+                // Octo has no mnemonic for machine code calls
+                "0x01 0x23",
+                "scroll-down 3",
+                "scroll-right",
+                "hires",
+                "va := 0x12",
+                "vb += 0x01",
+                "v1 += v2",
+                "v1 >>= v2",
+                "if v1 != 0x05 then",
+                "if v1 == v2 then",
+                "if v1 -key then",
+                "v3 := random 0x0F",
+                "v3 := key",
+                "bcd v5",
+                "save v1 - v2",
+                "plane 2",
+                "i := long 0x1234",
+                "sub_22E",
+                "jump0 0x300",
+                "0xAB 0xCD 0xEF 0x01",
+                ": sub_22E",
+                "return",
+                "0x7F",
+            ]
+        );
+    }
+
+    #[test]
+    fn sprites_are_drawn_in_the_comments() {
+        let rom = [0xA2, 0x06, 0xD0, 0x12, 0x12, 0x00, 0xF0, 0x90, 0x55];
+        let classic = disassemble(&rom, Syntax::Classic);
+        assert!(classic.contains("\nsprite_206:\n    db 0b11110000           ; 206: ####....\n"));
+        assert!(classic.contains("    db 0b10010000           ; 207: #..#....\n"));
+        // Past the sprite's height it's plain data again
+        assert!(classic.contains("    db 0x55                 ; 208:\n"));
+
+        let octo = disassemble(&rom, Syntax::Octo);
+        assert!(octo.contains("    i := sprite_206         # 200: A206\n"));
+        assert!(octo.contains("\n: sprite_206\n    0b11110000              # 206: ####....\n"));
+    }
+
+    #[test]
+    fn long_lines_keep_a_space_before_the_comment() {
+        let text = disassemble(&[0x12, 0x00, 1, 2, 3, 4, 5, 6, 7, 8], Syntax::Classic);
+        assert!(text.contains("db 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08 ; 202:\n"));
+    }
+
+    #[test]
+    fn assembles_back_to_the_same_rom() {
+        assert_eq!(
+            crate::assemble(&disassemble(ROM, Syntax::Classic)).unwrap(),
+            ROM
+        );
+        assert_eq!(
+            crate::octo::compile(&disassemble(ROM, Syntax::Octo)).unwrap(),
+            ROM
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::{error::Error, fmt};
+
+/// Something that went wrong while loading or running a program.
+///
+/// Errors raised while executing carry the address of the faulting instruction, and the
+/// program counter is left pointing at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    StackUnderflow { pc: u16 },
+    StackOverflow { pc: u16 },
+    RomTooLarge { size: usize, max: usize },
+    MemoryOutOfBounds { addr: usize, pc: u16 },
+    UnknownOpcode { opcode: u16, pc: u16 },
+    InvalidSaveState(&'static str),
+    UnsupportedSaveStateVersion(u16),
+    SaveStateRomMismatch,
+    InvalidMovie(&'static str),
+    UnsupportedMovieVersion(u16),
+    NoSuchMovieFrame { index: usize, frames: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::StackUnderflow { pc } => {
+                write!(f, "return with an empty stack at {:#05X}", pc)
+            }
+            EmulatorError::StackOverflow { pc } => {
+                write!(f, "call stack overflowed at {:#05X}", pc)
+            }
+            EmulatorError::RomTooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes but at most {} bytes can be loaded",
+                size, max
+            ),
+            EmulatorError::MemoryOutOfBounds { addr, pc } => write!(
+                f,
+                "memory access at {:#06X} is out of bounds at {:#05X}",
+                addr, pc
+            ),
+            EmulatorError::UnknownOpcode { opcode, pc } => {
+                write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            EmulatorError::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            EmulatorError::UnsupportedSaveStateVersion(version) => {
+                write!(f, "save state version {} is not supported", version)
+            }
+            EmulatorError::SaveStateRomMismatch => {
+                write!(f, "save state was made with a different ROM")
+            }
+            EmulatorError::InvalidMovie(reason) => write!(f, "invalid movie: {}", reason),
+            EmulatorError::UnsupportedMovieVersion(version) => {
+                write!(f, "movie version {} is not supported", version)
+            }
+            EmulatorError::NoSuchMovieFrame { index, frames } => {
+                write!(f, "movie has no frame {}, only {} frames", index, frames)
+            }
+        }
+    }
+}
+
+impl Error for EmulatorError {}
@@ -0,0 +1,120 @@
+use std::{
+    f32::consts::PI,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rodio::{Sink, Source};
+
+use crate::hardware::{AudioSink, NullAudio};
+
+const SAMPLE_RATE: u32 = 48000;
+const TONE_FREQUENCY: f32 = 440.0;
+
+#[derive(Clone, Copy)]
+struct Waveform {
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+}
+
+/// An endless source playing whatever waveform the program last asked for: a plain sine
+/// until an XO-CHIP program loads its own 128 bit pattern.
+struct Tone {
+    waveform: Arc<Mutex<Waveform>>,
+    num_sample: usize,
+    position: f32,
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let waveform = *self.waveform.lock().unwrap();
+        self.num_sample = self.num_sample.wrapping_add(1);
+
+        let value = match waveform.pattern {
+            None => {
+                let t = self.num_sample as f32 / SAMPLE_RATE as f32;
+                (2.0 * PI * TONE_FREQUENCY * t).sin()
+            }
+            Some(pattern) => {
+                let rate = 4000.0 * 2f32.powf((waveform.pitch as f32 - 64.0) / 48.0);
+                self.position = (self.position + rate / SAMPLE_RATE as f32) % 128.0;
+
+                let bit = self.position as usize;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    0.5
+                } else {
+                    -0.5
+                }
+            }
+        };
+
+        Some(value)
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+struct Beeper {
+    sink: Sink,
+    waveform: Arc<Mutex<Waveform>>,
+}
+
+impl AudioSink for Beeper {
+    fn start_tone(&mut self) {
+        self.sink.play();
+    }
+
+    fn stop_tone(&mut self) {
+        self.sink.pause();
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        *self.waveform.lock().unwrap() = Waveform {
+            pattern: Some(*pattern),
+            pitch,
+        };
+    }
+}
+
+pub fn setup_audio() -> Box<dyn AudioSink> {
+    let device = match rodio::default_output_device() {
+        Some(device) => device,
+        None => {
+            eprintln!("No audio device found, running without sound");
+            return Box::new(NullAudio);
+        }
+    };
+
+    let waveform = Arc::new(Mutex::new(Waveform {
+        pattern: None,
+        pitch: 64,
+    }));
+
+    let sink = Sink::new(&device);
+    sink.append(Tone {
+        waveform: Arc::clone(&waveform),
+        num_sample: 0,
+        position: 0.0,
+    });
+    sink.pause();
+
+    Box::new(Beeper { sink, waveform })
+}
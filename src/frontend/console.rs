@@ -0,0 +1,233 @@
+use std::{
+    io::{self, BufRead},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::debugger::{
+    parse_number, Condition, Register, StopReason, WatchAccess, WatchHit, Watchpoint,
+};
+use crate::Chip8;
+
+const HELP: &str = "\
+Commands:
+  pause | p                     pause execution
+  continue | c                  resume execution
+  step | s                      run one instruction
+  next | n                      run one instruction, stepping over calls
+  finish | f                    run until the current subroutine returns
+  until <addr> | u <addr>       run until the program counter reaches an address
+  break <addr> [if <cond>]      set a breakpoint, e.g. `break 0x2A4 if V3 == 5`
+  delete [<addr>]               remove a breakpoint, or all of them
+  breakpoints | bl              list breakpoints
+  watch <addr>[-<end>]          stop when an instruction writes memory in the range
+  rwatch <addr>[-<end>]         stop when an instruction reads memory in the range
+  awatch <addr>[-<end>]         stop on either
+  watch <reg> [<op> <value>]    stop when a register changes, e.g. `watch VF == 1`
+  unwatch [<n>]                 remove a watchpoint by number, or all of them
+  watchpoints | wl              list watchpoints
+  regs | r                      show registers
+  help | h                      show this message";
+
+/// A debugger command line read from stdin alongside the window.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Self {
+        let (sender, lines) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        eprintln!("Debugger ready, type `help` for a list of commands");
+
+        Console { lines }
+    }
+
+    /// Runs every command typed since the last call.
+    pub fn handle_commands(&self, chip8: &mut Chip8) {
+        while let Ok(line) = self.lines.try_recv() {
+            if let Err(err) = execute(line.trim(), chip8) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    pub fn report_stop(&self, chip8: &mut Chip8) {
+        let reason = match chip8.debugger_mut().take_stop() {
+            Some(reason) => reason,
+            None => return,
+        };
+
+        match reason {
+            StopReason::Paused => eprintln!("Paused"),
+            StopReason::Breakpoint(address) => eprintln!("Breakpoint at {:#05X}", address),
+            StopReason::Step => (),
+            StopReason::Fault(err) => eprintln!("Fault: {}", err),
+            StopReason::Watchpoint { pc, hit } => match hit {
+                WatchHit::Read { address, value } => eprintln!(
+                    "Watchpoint: {:#05X} read {:#04X} at {:#05X}",
+                    pc, value, address
+                ),
+                WatchHit::Write { address, value } => eprintln!(
+                    "Watchpoint: {:#05X} wrote {:#04X} to {:#05X}",
+                    pc, value, address
+                ),
+                WatchHit::Register { register, old, new } => eprintln!(
+                    "Watchpoint: {:#05X} changed {} from {:#X} to {:#X}",
+                    pc, register, old, new
+                ),
+            },
+            StopReason::TimerWatchpoint { register, old, new } => eprintln!(
+                "Watchpoint: the timers changed {} from {:#X} to {:#X}",
+                register, old, new
+            ),
+        }
+        print_location(chip8);
+    }
+}
+
+fn print_location(chip8: &Chip8) {
+    let cpu = chip8.cpu();
+    match cpu.next_instruction() {
+        Some(instr) => eprintln!("{:#05X}: {}", cpu.program_counter(), instr),
+        None => eprintln!("{:#05X}: unknown instruction", cpu.program_counter()),
+    }
+}
+
+fn print_registers(chip8: &Chip8) {
+    let cpu = chip8.cpu();
+    for (row, registers) in cpu.registers().chunks(8).enumerate() {
+        let line: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", row * 8 + i, value))
+            .collect();
+        eprintln!("{}", line.join(" "));
+    }
+    let stack: Vec<String> = cpu
+        .stack()
+        .iter()
+        .map(|address| format!("{:03X}", address))
+        .collect();
+    eprintln!(
+        "PC={:03X} I={:03X} DT={:02X} ST={:02X} stack=[{}]",
+        cpu.program_counter(),
+        cpu.address_register(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        stack.join(" ")
+    );
+}
+
+fn parse_watchpoint(s: &str, access: WatchAccess) -> Result<Watchpoint, String> {
+    if access == WatchAccess::Write {
+        if let Ok(condition) = s.parse::<Condition>() {
+            return Ok(Watchpoint::Register {
+                register: condition.register,
+                condition: Some(condition),
+            });
+        }
+        if let Ok(register) = s.parse::<Register>() {
+            return Ok(Watchpoint::Register {
+                register,
+                condition: None,
+            });
+        }
+    }
+
+    let (start, end) = match s.find('-') {
+        Some(index) => (
+            parse_number(s[..index].trim())?,
+            parse_number(s[index + 1..].trim())?,
+        ),
+        None => {
+            let address = parse_number(s)?;
+            (address, address)
+        }
+    };
+    if end < start {
+        return Err(format!("Range ends before it starts: {}", s));
+    }
+
+    Ok(Watchpoint::Memory { start, end, access })
+}
+
+fn execute(line: &str, chip8: &mut Chip8) -> Result<(), String> {
+    let mut words = line.splitn(2, char::is_whitespace);
+    let command = words.next().unwrap_or("");
+    let rest = words.next().unwrap_or("").trim();
+
+    match command {
+        "" => (),
+        "pause" | "p" => chip8.pause(),
+        "continue" | "c" => chip8.resume(),
+        "step" | "s" => chip8.step_into(),
+        "next" | "n" => chip8.step_over(),
+        "finish" | "f" => chip8.step_out(),
+        "until" | "u" => chip8.run_to(parse_number(rest)?),
+        "break" | "b" => {
+            let (address, condition) = match rest.find(" if ") {
+                Some(index) => (
+                    &rest[..index],
+                    Some(rest[index + 4..].parse::<Condition>()?),
+                ),
+                None => (rest, None),
+            };
+            let address = parse_number(address.trim())?;
+            chip8.debugger_mut().add_breakpoint(address, condition);
+        }
+        "delete" | "d" if rest.is_empty() => chip8.debugger_mut().clear_breakpoints(),
+        "delete" | "d" => {
+            let address = parse_number(rest)?;
+            if !chip8.debugger_mut().remove_breakpoint(address) {
+                return Err(format!("No breakpoint at {:#05X}", address));
+            }
+        }
+        "breakpoints" | "bl" => {
+            for bp in chip8.debugger().breakpoints() {
+                match bp.condition {
+                    Some(condition) => eprintln!("{:#05X} if {}", bp.address, condition),
+                    None => eprintln!("{:#05X}", bp.address),
+                }
+            }
+        }
+        "watch" | "rwatch" | "awatch" => {
+            let access = match command {
+                "rwatch" => WatchAccess::Read,
+                "awatch" => WatchAccess::ReadWrite,
+                _ => WatchAccess::Write,
+            };
+            let watchpoint = parse_watchpoint(rest, access)?;
+            chip8.debugger_mut().add_watchpoint(watchpoint);
+        }
+        "unwatch" if rest.is_empty() => chip8.debugger_mut().clear_watchpoints(),
+        "unwatch" => {
+            let number = parse_number(rest)?;
+            if !chip8.debugger_mut().remove_watchpoint(number as usize) {
+                return Err(format!("No watchpoint {}", number));
+            }
+        }
+        "watchpoints" | "wl" => {
+            for (number, wp) in chip8.debugger().watchpoints().iter().enumerate() {
+                eprintln!("{}: {}", number, wp);
+            }
+        }
+        "regs" | "r" => print_registers(chip8),
+        "help" | "h" => eprintln!("{}", HELP),
+        _ => return Err(format!("Unknown command: {}", command)),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+
+use super::keymap::rom_settings;
+use crate::json::Json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    /// The bottom face button: A on Xbox pads, cross on PlayStation ones.
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+const BUTTONS: [(&str, Button); 12] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("West", Button::West),
+    ("North", Button::North),
+    ("LeftShoulder", Button::LeftShoulder),
+    ("RightShoulder", Button::RightShoulder),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+/// Axes run from -1.0 to 1.0, with up and left negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    LeftStickX,
+    LeftStickY,
+    /// D-pads that report themselves as a hat rather than as four buttons.
+    DPadX,
+    DPadY,
+}
+
+/// Something happening on one of the connected gamepads, each known by a number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadEvent {
+    Connected(usize),
+    Disconnected(usize),
+    Button(usize, Button, bool),
+    Axis(usize, Axis, f32),
+}
+
+/// Where gamepad events come from. Sources never block; `poll` returns `None` once there's
+/// nothing new.
+pub trait GamepadSource {
+    fn poll(&mut self) -> Option<GamepadEvent>;
+}
+
+/// A source for hosts without gamepad support.
+#[cfg(not(target_os = "linux"))]
+pub struct NoGamepads;
+
+#[cfg(not(target_os = "linux"))]
+impl GamepadSource for NoGamepads {
+    fn poll(&mut self) -> Option<GamepadEvent> {
+        None
+    }
+}
+
+const DEFAULT_DEADZONE: f32 = 0.35;
+
+/// Which keypad key each gamepad button presses. The left stick works as the D-pad.
+///
+/// By default the D-pad is 2, 4, 6 and 8 and the South button 5, which covers most games,
+/// with the other face buttons on 0, A and B and Start and Select on F and E.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamepadProfile {
+    keys: [Option<u8>; 12],
+    /// How far the stick has to be pushed along an axis before it counts as a direction.
+    deadzone: f32,
+}
+
+impl GamepadProfile {
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.keys[button as usize]
+    }
+
+    pub fn bind(&mut self, button: Button, key: Option<u8>) {
+        self.keys[button as usize] = key;
+    }
+
+    /// Binds the D-pad and the South and East buttons to the keys a ROM database entry says
+    /// the game uses, e.g. `up` or `player1A`.
+    pub fn apply_key_hints(&mut self, hints: &[(String, u8)]) {
+        for (hint, key) in hints {
+            let button = match hint
+                .trim_start_matches("player1")
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "up" => Button::DPadUp,
+                "down" => Button::DPadDown,
+                "left" => Button::DPadLeft,
+                "right" => Button::DPadRight,
+                "a" => Button::South,
+                "b" => Button::East,
+                _ => continue,
+            };
+            self.bind(button, Some(*key));
+        }
+    }
+
+    /// Applies the `gamepad` settings of a keymap file, first the file's and then the ROM's:
+    ///
+    /// ```json
+    /// "gamepad": { "deadzone": 0.25, "buttons": { "South": "5", "East": null } }
+    /// ```
+    ///
+    /// Each button listed is rebound to the keypad key given, or to nothing if `null`.
+    pub fn apply_config(
+        &mut self,
+        text: &str,
+        rom_name: &str,
+        rom_hash: &str,
+    ) -> Result<(), String> {
+        let config = Json::parse(text)?;
+        let rom = rom_settings(&config, rom_name, rom_hash);
+
+        for section in [Some(&config), rom].iter().flatten() {
+            if let Some(gamepad) = section.get("gamepad") {
+                self.apply(gamepad)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, gamepad: &Json) -> Result<(), String> {
+        if let Some(deadzone) = gamepad.get("deadzone") {
+            self.deadzone = deadzone
+                .as_f64()
+                .filter(|deadzone| (0.0..1.0).contains(deadzone))
+                .ok_or("\"deadzone\" should be a number from 0 to 1")?
+                as f32;
+        }
+
+        let buttons = match gamepad.get("buttons") {
+            Some(buttons) => buttons
+                .as_object()
+                .ok_or("\"buttons\" should be an object")?,
+            None => return Ok(()),
+        };
+        for (name, key) in buttons {
+            let button = BUTTONS
+                .iter()
+                .find(|(button_name, _)| button_name.eq_ignore_ascii_case(name))
+                .map(|&(_, button)| button)
+                .ok_or_else(|| format!("Unknown gamepad button: {}", name))?;
+            let keypad_key = match key {
+                Json::Null => None,
+                _ => Some(
+                    key.as_str()
+                        .filter(|key| key.len() == 1)
+                        .and_then(|key| u8::from_str_radix(key, 16).ok())
+                        .ok_or_else(|| format!("Unknown keypad key: {}", key))?,
+                ),
+            };
+            self.bind(button, keypad_key);
+        }
+        Ok(())
+    }
+}
+
+impl Default for GamepadProfile {
+    fn default() -> Self {
+        let mut profile = GamepadProfile {
+            keys: [None; 12],
+            deadzone: DEFAULT_DEADZONE,
+        };
+        let defaults = [
+            (Button::DPadUp, 0x2),
+            (Button::DPadLeft, 0x4),
+            (Button::DPadRight, 0x6),
+            (Button::DPadDown, 0x8),
+            (Button::South, 0x5),
+            (Button::East, 0x0),
+            (Button::West, 0xA),
+            (Button::North, 0xB),
+            (Button::Select, 0xE),
+            (Button::Start, 0xF),
+        ];
+        for &(button, key) in defaults.iter() {
+            profile.bind(button, Some(key));
+        }
+        profile
+    }
+}
+
+#[derive(Default)]
+struct Pad {
+    buttons: [bool; 12],
+    stick: (f32, f32),
+}
+
+impl Pad {
+    fn held(&self, button: Button, deadzone: f32) -> bool {
+        let (x, y) = self.stick;
+        self.buttons[button as usize]
+            || match button {
+                Button::DPadUp => y < -deadzone,
+                Button::DPadDown => y > deadzone,
+                Button::DPadLeft => x < -deadzone,
+                Button::DPadRight => x > deadzone,
+                _ => false,
+            }
+    }
+}
+
+/// Every connected gamepad, pressing keypad keys through a profile. Gamepads can come and go
+/// while running; one that's unplugged lets go of everything it held.
+pub struct Gamepads {
+    source: Box<dyn GamepadSource>,
+    profile: GamepadProfile,
+    pads: BTreeMap<usize, Pad>,
+}
+
+impl Gamepads {
+    pub fn new(source: Box<dyn GamepadSource>, profile: GamepadProfile) -> Self {
+        Gamepads {
+            source,
+            profile,
+            pads: BTreeMap::new(),
+        }
+    }
+
+    /// Takes in every event since the last call, returning whether there were any.
+    pub fn update(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(event) = self.source.poll() {
+            changed = true;
+            match event {
+                GamepadEvent::Connected(id) => {
+                    eprintln!("Gamepad {} connected", id);
+                    self.pads.insert(id, Pad::default());
+                }
+                GamepadEvent::Disconnected(id) => {
+                    eprintln!("Gamepad {} disconnected", id);
+                    self.pads.remove(&id);
+                }
+                GamepadEvent::Button(id, button, pressed) => {
+                    let pad = self.pads.entry(id).or_default();
+                    pad.buttons[button as usize] = pressed;
+                }
+                GamepadEvent::Axis(id, axis, value) => {
+                    let pad = self.pads.entry(id).or_default();
+                    let value = value.clamp(-1.0, 1.0);
+                    match axis {
+                        Axis::LeftStickX => pad.stick.0 = value,
+                        Axis::LeftStickY => pad.stick.1 = value,
+                        Axis::DPadX => {
+                            pad.buttons[Button::DPadLeft as usize] = value < -0.5;
+                            pad.buttons[Button::DPadRight as usize] = value > 0.5;
+                        }
+                        Axis::DPadY => {
+                            pad.buttons[Button::DPadUp as usize] = value < -0.5;
+                            pad.buttons[Button::DPadDown as usize] = value > 0.5;
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Whether any gamepad holds a button bound to the keypad key.
+    pub fn held(&self, key: u8) -> bool {
+        BUTTONS
+            .iter()
+            .filter(|&&(_, button)| self.profile.key(button) == Some(key))
+            .any(|&(_, button)| {
+                self.pads
+                    .values()
+                    .any(|pad| pad.held(button, self.profile.deadzone))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// A source fed by the test, standing in for real gamepads.
+    #[derive(Clone, Default)]
+    struct MockSource(Rc<RefCell<VecDeque<GamepadEvent>>>);
+
+    impl MockSource {
+        fn send(&self, event: GamepadEvent) {
+            self.0.borrow_mut().push_back(event);
+        }
+    }
+
+    impl GamepadSource for MockSource {
+        fn poll(&mut self) -> Option<GamepadEvent> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    fn gamepads(profile: GamepadProfile) -> (Gamepads, MockSource) {
+        let source = MockSource::default();
+        (Gamepads::new(Box::new(source.clone()), profile), source)
+    }
+
+    fn held_keys(gamepads: &Gamepads) -> Vec<u8> {
+        (0..16).filter(|&key| gamepads.held(key)).collect()
+    }
+
+    #[test]
+    fn buttons_press_keys() {
+        let (mut gamepads, source) = gamepads(GamepadProfile::default());
+        assert!(!gamepads.update());
+
+        source.send(GamepadEvent::Connected(0));
+        source.send(GamepadEvent::Button(0, Button::South, true));
+        source.send(GamepadEvent::Button(0, Button::DPadUp, true));
+        assert!(gamepads.update());
+        assert_eq!(held_keys(&gamepads), vec![0x2, 0x5]);
+
+        source.send(GamepadEvent::Button(0, Button::South, false));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0x2]);
+    }
+
+    #[test]
+    fn stick_and_hat_work_as_the_dpad() {
+        let (mut gamepads, source) = gamepads(GamepadProfile::default());
+        source.send(GamepadEvent::Connected(0));
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickX, 0.2));
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickY, -0.2));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![]);
+
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickX, -0.9));
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickY, 0.7));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0x4, 0x8]);
+
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickX, 0.0));
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickY, 0.0));
+        source.send(GamepadEvent::Axis(0, Axis::DPadX, 1.0));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0x6]);
+    }
+
+    #[test]
+    fn unplugging_lets_go() {
+        let (mut gamepads, source) = gamepads(GamepadProfile::default());
+        source.send(GamepadEvent::Connected(0));
+        source.send(GamepadEvent::Connected(1));
+        source.send(GamepadEvent::Button(0, Button::Start, true));
+        source.send(GamepadEvent::Button(1, Button::Start, true));
+        source.send(GamepadEvent::Button(1, Button::North, true));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0xB, 0xF]);
+
+        source.send(GamepadEvent::Disconnected(1));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0xF]);
+
+        source.send(GamepadEvent::Connected(1));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![0xF]);
+    }
+
+    #[test]
+    fn key_hints_move_the_dpad() {
+        let mut profile = GamepadProfile::default();
+        let hints = [
+            ("up", 0x1),
+            ("down", 0x4),
+            ("player1A", 0xC),
+            ("player2Up", 0xD),
+        ];
+        let hints: Vec<_> = hints
+            .iter()
+            .map(|&(hint, key)| (hint.to_string(), key))
+            .collect();
+        profile.apply_key_hints(&hints);
+
+        assert_eq!(profile.key(Button::DPadUp), Some(0x1));
+        assert_eq!(profile.key(Button::DPadDown), Some(0x4));
+        assert_eq!(profile.key(Button::DPadLeft), Some(0x4));
+        assert_eq!(profile.key(Button::South), Some(0xC));
+    }
+
+    #[test]
+    fn profiles_come_from_the_keymap_file() {
+        let config = r#"{
+            "gamepad": { "deadzone": 0.5, "buttons": { "South": "a" } },
+            "roms": {
+                "breakout.ch8": { "gamepad": { "buttons": { "DPadLeft": "4", "DPadRight": "6", "DPadUp": null } } }
+            }
+        }"#;
+
+        let mut profile = GamepadProfile::default();
+        profile.apply_config(config, "breakout.ch8", "").unwrap();
+        assert_eq!(profile.key(Button::South), Some(0xA));
+        assert_eq!(profile.key(Button::DPadUp), None);
+        assert_eq!(profile.key(Button::DPadDown), Some(0x8));
+
+        let (mut gamepads, source) = gamepads(profile);
+        source.send(GamepadEvent::Axis(0, Axis::LeftStickX, 0.4));
+        gamepads.update();
+        assert_eq!(held_keys(&gamepads), vec![]);
+
+        let error = |config| {
+            GamepadProfile::default()
+                .apply_config(config, "a.ch8", "")
+                .unwrap_err()
+        };
+        assert_eq!(
+            error(r#"{"gamepad": {"buttons": {"Turbo": "1"}}}"#),
+            "Unknown gamepad button: Turbo"
+        );
+        assert_eq!(
+            error(r#"{"gamepad": {"buttons": {"South": "10"}}}"#),
+            "Unknown keypad key: \"10\""
+        );
+    }
+}
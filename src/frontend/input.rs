@@ -0,0 +1,42 @@
+use winit_input_helper::WinitInputHelper;
+
+use super::gamepad::Gamepads;
+use super::keymap::Keymap;
+use crate::Keyboard;
+
+/// Updates the keypad after keyboard input. A keypad key tapped within one update is still
+/// seen as pressed for the frame.
+pub fn handle_input(
+    keyboard: &mut Keyboard,
+    keymap: &Keymap,
+    gamepads: &Gamepads,
+    input: &WinitInputHelper,
+) {
+    for key in 0..16 {
+        let tapped = keymap
+            .host_keys(key)
+            .iter()
+            .any(|&host_key| input.key_pressed(host_key));
+        if tapped {
+            keyboard.set_key(key, true);
+        }
+    }
+    update_keys(keyboard, keymap, gamepads, input);
+}
+
+/// Holds each keypad key while any of its host keys or gamepad buttons are held.
+pub fn update_keys(
+    keyboard: &mut Keyboard,
+    keymap: &Keymap,
+    gamepads: &Gamepads,
+    input: &WinitInputHelper,
+) {
+    for key in 0..16 {
+        let held = keymap
+            .host_keys(key)
+            .iter()
+            .any(|&host_key| input.key_held(host_key))
+            || gamepads.held(key);
+        keyboard.set_key(key, held);
+    }
+}
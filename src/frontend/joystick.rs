@@ -0,0 +1,117 @@
+use std::{
+    fs::File,
+    io::Read,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::gamepad::{Axis, Button, GamepadEvent, GamepadSource};
+
+/// How many of `/dev/input/js0`, `js1`, ... to look for.
+const DEVICES: usize = 4;
+/// How often to look for newly plugged in gamepads.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+const EVENT_BUTTON: u8 = 0x01;
+const EVENT_AXIS: u8 = 0x02;
+/// Set on the events sent when a device is opened, describing its state at the time.
+const EVENT_INIT: u8 = 0x80;
+
+/// Button numbers as the Linux `xpad` driver reports them, which most other drivers follow
+/// for the face buttons.
+const BUTTONS: [(u8, Button); 12] = [
+    (0, Button::South),
+    (1, Button::East),
+    (2, Button::West),
+    (3, Button::North),
+    (4, Button::LeftShoulder),
+    (5, Button::RightShoulder),
+    (6, Button::Select),
+    (7, Button::Start),
+    (11, Button::DPadLeft),
+    (12, Button::DPadRight),
+    (13, Button::DPadUp),
+    (14, Button::DPadDown),
+];
+
+const AXES: [(u8, Axis); 4] = [
+    (0, Axis::LeftStickX),
+    (1, Axis::LeftStickY),
+    (6, Axis::DPadX),
+    (7, Axis::DPadY),
+];
+
+/// Gamepads read through the Linux joystick API, each device on a thread of its own.
+///
+/// A thread looks for new devices every second, so gamepads can be plugged in while
+/// running. A device that can no longer be read has been unplugged.
+pub struct Joysticks {
+    events: Receiver<GamepadEvent>,
+}
+
+impl Joysticks {
+    pub fn spawn() -> Self {
+        let (sender, events) = mpsc::channel();
+        let connected = Arc::new(Mutex::new([false; DEVICES]));
+
+        thread::spawn(move || loop {
+            for id in 0..DEVICES {
+                if connected.lock().unwrap()[id] {
+                    continue;
+                }
+                if let Ok(device) = File::open(format!("/dev/input/js{}", id)) {
+                    connected.lock().unwrap()[id] = true;
+                    if sender.send(GamepadEvent::Connected(id)).is_err() {
+                        return;
+                    }
+                    let sender = sender.clone();
+                    let connected = Arc::clone(&connected);
+                    thread::spawn(move || {
+                        read_events(id, device, &sender);
+                        connected.lock().unwrap()[id] = false;
+                        let _ = sender.send(GamepadEvent::Disconnected(id));
+                    });
+                }
+            }
+            thread::sleep(SCAN_INTERVAL);
+        });
+
+        Joysticks { events }
+    }
+}
+
+impl GamepadSource for Joysticks {
+    fn poll(&mut self) -> Option<GamepadEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+/// Passes on a device's events until it goes away.
+fn read_events(id: usize, mut device: File, sender: &Sender<GamepadEvent>) {
+    // struct js_event { u32 time; i16 value; u8 type; u8 number; }
+    let mut event = [0; 8];
+    while device.read_exact(&mut event).is_ok() {
+        let value = i16::from_ne_bytes([event[4], event[5]]);
+        let number = event[7];
+        let gamepad_event = match event[6] & !EVENT_INIT {
+            EVENT_BUTTON => BUTTONS
+                .iter()
+                .find(|&&(button_number, _)| button_number == number)
+                .map(|&(_, button)| GamepadEvent::Button(id, button, value != 0)),
+            EVENT_AXIS => AXES
+                .iter()
+                .find(|&&(axis_number, _)| axis_number == number)
+                .map(|&(_, axis)| GamepadEvent::Axis(id, axis, value as f32 / i16::MAX as f32)),
+            _ => None,
+        };
+        if let Some(gamepad_event) = gamepad_event {
+            if sender.send(gamepad_event).is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::path::Path;
+
+use winit::event::VirtualKeyCode;
+
+use crate::json::Json;
+
+/// The keypad's keys row by row, the order layouts list their host keys in:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Built in layouts, each putting the keypad in the same place on the host keyboard.
+const LAYOUTS: [(&str, [VirtualKeyCode; 16]); 4] = {
+    use VirtualKeyCode::*;
+    [
+        (
+            "qwerty",
+            [Key1, Key2, Key3, Key4, Q, W, E, R, A, S, D, F, Z, X, C, V],
+        ),
+        (
+            "azerty",
+            [Key1, Key2, Key3, Key4, A, Z, E, R, Q, S, D, F, W, X, C, V],
+        ),
+        (
+            "dvorak",
+            [
+                Key1, Key2, Key3, Key4, Apostrophe, Comma, Period, P, A, O, E, U, Semicolon, Q, J,
+                K,
+            ],
+        ),
+        (
+            "numpad",
+            [
+                Numpad7,
+                Numpad8,
+                Numpad9,
+                NumpadDivide,
+                Numpad4,
+                Numpad5,
+                Numpad6,
+                NumpadMultiply,
+                Numpad1,
+                Numpad2,
+                Numpad3,
+                NumpadSubtract,
+                Numpad0,
+                NumpadDecimal,
+                NumpadEnter,
+                NumpadAdd,
+            ],
+        ),
+    ]
+};
+
+/// The host keys a keymap file can name, spelled as winit spells them.
+const HOST_KEYS: [(&str, VirtualKeyCode); 78] = {
+    use VirtualKeyCode::*;
+    [
+        ("Key0", Key0),
+        ("Key1", Key1),
+        ("Key2", Key2),
+        ("Key3", Key3),
+        ("Key4", Key4),
+        ("Key5", Key5),
+        ("Key6", Key6),
+        ("Key7", Key7),
+        ("Key8", Key8),
+        ("Key9", Key9),
+        ("A", A),
+        ("B", B),
+        ("C", C),
+        ("D", D),
+        ("E", E),
+        ("F", F),
+        ("G", G),
+        ("H", H),
+        ("I", I),
+        ("J", J),
+        ("K", K),
+        ("L", L),
+        ("M", M),
+        ("N", N),
+        ("O", O),
+        ("P", P),
+        ("Q", Q),
+        ("R", R),
+        ("S", S),
+        ("T", T),
+        ("U", U),
+        ("V", V),
+        ("W", W),
+        ("X", X),
+        ("Y", Y),
+        ("Z", Z),
+        ("Numpad0", Numpad0),
+        ("Numpad1", Numpad1),
+        ("Numpad2", Numpad2),
+        ("Numpad3", Numpad3),
+        ("Numpad4", Numpad4),
+        ("Numpad5", Numpad5),
+        ("Numpad6", Numpad6),
+        ("Numpad7", Numpad7),
+        ("Numpad8", Numpad8),
+        ("Numpad9", Numpad9),
+        ("NumpadAdd", NumpadAdd),
+        ("NumpadSubtract", NumpadSubtract),
+        ("NumpadMultiply", NumpadMultiply),
+        ("NumpadDivide", NumpadDivide),
+        ("NumpadDecimal", NumpadDecimal),
+        ("NumpadEnter", NumpadEnter),
+        ("Up", Up),
+        ("Down", Down),
+        ("Left", Left),
+        ("Right", Right),
+        ("Space", Space),
+        ("Return", Return),
+        ("Tab", Tab),
+        ("LShift", LShift),
+        ("RShift", RShift),
+        ("LControl", LControl),
+        ("RControl", RControl),
+        ("LAlt", LAlt),
+        ("RAlt", RAlt),
+        ("Apostrophe", Apostrophe),
+        ("Comma", Comma),
+        ("Period", Period),
+        ("Semicolon", Semicolon),
+        ("Slash", Slash),
+        ("Backslash", Backslash),
+        ("LBracket", LBracket),
+        ("RBracket", RBracket),
+        ("Grave", Grave),
+        ("Insert", Insert),
+        ("Delete", Delete),
+        ("Home", Home),
+        ("End", End),
+    ]
+};
+
+fn host_key(name: &str) -> Option<VirtualKeyCode> {
+    HOST_KEYS
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+fn host_key_name(key: VirtualKeyCode) -> &'static str {
+    HOST_KEYS
+        .iter()
+        .find(|&&(_, host_key)| host_key == key)
+        .map(|&(name, _)| name)
+        .expect("every bindable key has a name")
+}
+
+/// The settings a keymap file has for one ROM, found by its file name or SHA-1.
+pub(super) fn rom_settings<'a>(
+    config: &'a Json,
+    rom_name: &str,
+    rom_hash: &str,
+) -> Option<&'a Json> {
+    let file_name = Path::new(rom_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(rom_name);
+    config
+        .get("roms")
+        .and_then(|roms| roms.get(file_name).or_else(|| roms.get(rom_hash)))
+}
+
+/// Which host keys press each key of the hex keypad. A key can have any number of host keys
+/// bound to it, and is held while any of them are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<VirtualKeyCode>; 16],
+}
+
+impl Keymap {
+    /// One of the built in layouts: `qwerty`, `azerty`, `dvorak` or `numpad`.
+    pub fn layout(name: &str) -> Option<Keymap> {
+        let (_, host_keys) = LAYOUTS
+            .iter()
+            .find(|(layout, _)| layout.eq_ignore_ascii_case(name))?;
+
+        let mut keymap = Keymap {
+            keys: Default::default(),
+        };
+        for (&key, &host_key) in KEYPAD_ORDER.iter().zip(host_keys.iter()) {
+            keymap.keys[key as usize] = vec![host_key];
+        }
+        Some(keymap)
+    }
+
+    pub fn host_keys(&self, key: u8) -> &[VirtualKeyCode] {
+        &self.keys[key as usize]
+    }
+
+    /// Replaces the host keys bound to a keypad key.
+    pub fn bind(&mut self, key: u8, host_keys: Vec<VirtualKeyCode>) {
+        self.keys[key as usize] = host_keys;
+    }
+
+    /// Reads a keymap file, picking out the settings for a ROM by its file name or SHA-1.
+    ///
+    /// ```json
+    /// {
+    ///   "layout": "azerty",
+    ///   "keys": { "5": ["Z", "Up"] },
+    ///   "roms": {
+    ///     "pong.ch8": { "keys": { "1": ["W"], "4": ["S"] } }
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// Everything is optional. The map starts from a layout: `layout` if given, otherwise the
+    /// ROM's own, then the file's, then QWERTY. Then `keys` rebinds individual keypad keys,
+    /// first the file's and then the ROM's.
+    pub fn from_config(
+        text: &str,
+        layout: Option<&str>,
+        rom_name: &str,
+        rom_hash: &str,
+    ) -> Result<Keymap, String> {
+        let config = Json::parse(text)?;
+        let rom = rom_settings(&config, rom_name, rom_hash);
+
+        let layout = match layout {
+            Some(name) => Some(name),
+            None => rom
+                .and_then(|rom| rom.get("layout"))
+                .or_else(|| config.get("layout"))
+                .map(|name| name.as_str().ok_or("\"layout\" should be a name"))
+                .transpose()?,
+        };
+        let mut keymap = match layout {
+            Some(name) => {
+                Keymap::layout(name).ok_or_else(|| format!("Unknown layout: {}", name))?
+            }
+            None => Keymap::default(),
+        };
+
+        for section in [Some(&config), rom].iter().flatten() {
+            if let Some(keys) = section.get("keys") {
+                keymap.bind_all(keys)?;
+            }
+        }
+        Ok(keymap)
+    }
+
+    fn bind_all(&mut self, keys: &Json) -> Result<(), String> {
+        let members = keys.as_object().ok_or("\"keys\" should be an object")?;
+        for (name, host_keys) in members {
+            let key = match u8::from_str_radix(name, 16) {
+                Ok(key) if name.len() == 1 => key,
+                _ => return Err(format!("Unknown keypad key: {}", name)),
+            };
+            let host_keys = host_keys
+                .as_array()
+                .ok_or_else(|| format!("Key {} should have a list of host keys", name))?
+                .iter()
+                .map(|host_key| {
+                    host_key
+                        .as_str()
+                        .and_then(self::host_key)
+                        .ok_or_else(|| format!("Unknown host key: {}", host_key))
+                })
+                .collect::<Result<_, _>>()?;
+            self.bind(key, host_keys);
+        }
+        Ok(())
+    }
+
+    /// The map as a keymap file, with every keypad key listed.
+    pub fn to_json(&self) -> Json {
+        let keys = KEYPAD_ORDER
+            .iter()
+            .map(|&key| {
+                let host_keys = self
+                    .host_keys(key)
+                    .iter()
+                    .map(|&host_key| Json::String(host_key_name(host_key).to_string()))
+                    .collect();
+                (format!("{:X}", key), Json::Array(host_keys))
+            })
+            .collect();
+        Json::Object(vec![("keys".to_string(), Json::Object(keys))])
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::layout("qwerty").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_bind_every_key_once() {
+        for (name, _) in LAYOUTS.iter() {
+            let keymap = Keymap::layout(name).unwrap();
+            for key in 0..16 {
+                assert_eq!(keymap.host_keys(key).len(), 1, "{} {:X}", name, key);
+            }
+        }
+    }
+
+    #[test]
+    fn rom_settings_override_the_file() {
+        let config = r#"{
+            "layout": "azerty",
+            "keys": { "5": ["Z", "Up"], "a": [] },
+            "roms": {
+                "pong.ch8": { "keys": { "1": ["W"] } },
+                "0123abcd": { "layout": "numpad" }
+            }
+        }"#;
+
+        let keymap = Keymap::from_config(config, None, "roms/pong.ch8", "ffff").unwrap();
+        assert_eq!(
+            keymap.host_keys(0x5),
+            &[VirtualKeyCode::Z, VirtualKeyCode::Up]
+        );
+        assert_eq!(keymap.host_keys(0x1), &[VirtualKeyCode::W]);
+        assert_eq!(keymap.host_keys(0x4), &[VirtualKeyCode::A]);
+        assert!(keymap.host_keys(0xA).is_empty());
+
+        let keymap = Keymap::from_config(config, None, "other.ch8", "0123abcd").unwrap();
+        assert_eq!(keymap.host_keys(0x1), &[VirtualKeyCode::Numpad7]);
+        assert_eq!(
+            keymap.host_keys(0x5),
+            &[VirtualKeyCode::Z, VirtualKeyCode::Up]
+        );
+
+        let keymap = Keymap::from_config(config, Some("dvorak"), "other.ch8", "0123abcd").unwrap();
+        assert_eq!(keymap.host_keys(0x1), &[VirtualKeyCode::Key1]);
+        assert_eq!(keymap.host_keys(0x4), &[VirtualKeyCode::Apostrophe]);
+    }
+
+    #[test]
+    fn reports_unknown_names() {
+        let error = |config| Keymap::from_config(config, None, "a.ch8", "").unwrap_err();
+        assert_eq!(error(r#"{"layout": "colemak"}"#), "Unknown layout: colemak");
+        assert_eq!(error(r#"{"keys": {"G": []}}"#), "Unknown keypad key: G");
+        assert_eq!(
+            error(r#"{"keys": {"1": ["Hyper"]}}"#),
+            "Unknown host key: \"Hyper\""
+        );
+    }
+
+    #[test]
+    fn dumped_map_loads_back() {
+        let mut keymap = Keymap::layout("dvorak").unwrap();
+        keymap.bind(0x5, vec![VirtualKeyCode::Comma, VirtualKeyCode::Up]);
+        let dumped = keymap.to_json().pretty();
+        assert_eq!(Keymap::from_config(&dumped, None, "a.ch8", ""), Ok(keymap));
+    }
+}
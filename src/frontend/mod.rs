@@ -0,0 +1,406 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::{
+    dpi::LogicalSize,
+    event::{Event, VirtualKeyCode},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+use winit_input_helper::WinitInputHelper;
+
+use crate::hardware::{DisplayTarget, Framebuffer, Palette};
+use crate::romdb::RomInfo;
+use crate::trace::{TraceFormat, TraceWriter};
+use crate::{
+    hash, octo, Chip8, Movie, Quirks, Rewind, Scheduler, DEFAULT_INSTRUCTIONS_PER_FRAME,
+    HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH,
+};
+
+mod audio;
+mod console;
+mod gamepad;
+mod input;
+#[cfg(target_os = "linux")]
+mod joystick;
+mod keymap;
+mod settings;
+mod slots;
+
+use gamepad::{GamepadProfile, GamepadSource, Gamepads};
+
+pub use keymap::Keymap;
+
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 320;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+const REWIND_KEY: VirtualKeyCode = VirtualKeyCode::Back;
+const SPEED_UP_KEY: VirtualKeyCode = VirtualKeyCode::Equals;
+const SLOW_DOWN_KEY: VirtualKeyCode = VirtualKeyCode::Minus;
+
+/// Settings for a session in the windowed frontend.
+pub struct Options {
+    pub rom_name: String,
+    /// The quirks to run with, or `None` for the ROM database's.
+    pub quirks: Option<Quirks>,
+    /// How many instructions to run in each 60Hz frame, or `None` for the ROM database's.
+    pub instructions_per_frame: Option<usize>,
+    /// A database file to read on top of the one in the config directory.
+    pub rom_db_file: Option<String>,
+    /// Save the quirks and speed given for this ROM to the local overrides.
+    pub remember: bool,
+    /// Count the timers down with real time rather than once per emulated frame, so they
+    /// keep time even when frames are dropped. Runs are no longer reproducible.
+    pub wall_clock_timers: bool,
+    /// Where the random numbers start from, or a different place each run if `None`.
+    pub seed: Option<u64>,
+    /// A JSON file of keypad bindings, see `Keymap::from_config`.
+    pub keymap_file: Option<String>,
+    /// The keyboard layout to lay the keypad out for, overriding the keymap file's.
+    pub layout: Option<String>,
+    /// How far back holding the rewind key can go, or 0 to turn rewinding off.
+    pub rewind_seconds: usize,
+    /// Start paused with a debugger console reading commands from stdin.
+    pub debug: bool,
+    /// Record the session's input to this movie file, to replay later. Rewinding and
+    /// save slots are turned off while recording.
+    pub record_file: Option<String>,
+    /// Record every instruction run to this file.
+    pub trace_file: Option<String>,
+    pub trace_format: TraceFormat,
+}
+
+struct Screen {
+    pixels: Pixels<Window>,
+    palette: Palette,
+}
+
+// The pixel buffer is always high resolution, with low resolution pixels drawn at double size
+impl DisplayTarget for Screen {
+    fn draw(&mut self, display: &Framebuffer) {
+        let scale = HIRES_DISPLAY_WIDTH / display.width();
+        let frame = self.pixels.get_frame();
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let x = (i % HIRES_DISPLAY_WIDTH) / scale;
+            let y = (i / HIRES_DISPLAY_WIDTH) / scale;
+            let [r, g, b] = self.palette.color(display.get_color(x, y));
+            pixel.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+}
+
+/// Counts how many 60Hz ticks of real time have passed, for `Options::wall_clock_timers`.
+struct WallClock {
+    last_tick: Instant,
+}
+
+impl WallClock {
+    fn new() -> Self {
+        WallClock {
+            last_tick: Instant::now(),
+        }
+    }
+
+    fn ticks(&mut self) -> usize {
+        let mut ticks = 0;
+        while self.last_tick.elapsed() >= FRAME_DURATION {
+            self.last_tick += FRAME_DURATION;
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+fn read_bytes_from_file(rom_name: &str) -> Result<Vec<u8>, String> {
+    fs::read(rom_name).map_err(|err| format!("Unable to open file {}: {}", rom_name, err))
+}
+
+/// What the ROM database knows about a ROM, reporting what it found.
+fn look_up_rom(options: &Options, rom_hash: &[u8; 20]) -> Result<RomInfo, String> {
+    let database = settings::load_database(options.rom_db_file.as_deref())?;
+    let info = database.lookup(rom_hash).cloned().unwrap_or_default();
+
+    if let Some(title) = &info.title {
+        match info.platform_name() {
+            Some(platform) => eprintln!("{} ({})", title, platform),
+            None => eprintln!("{}", title),
+        }
+    }
+    if !info.keys.is_empty() {
+        let keys: Vec<_> = info
+            .keys
+            .iter()
+            .map(|(hint, key)| format!("{} {:X}", hint, key))
+            .collect();
+        eprintln!("Keys: {}", keys.join(", "));
+    }
+
+    Ok(info)
+}
+
+fn setup_machine(options: &Options) -> Result<(Chip8, RomInfo, Option<Movie>), Box<dyn Error>> {
+    // Octo source is compiled on the fly
+    let rom_data = if options.rom_name.ends_with(".8o") {
+        octo::compile_file(Path::new(&options.rom_name))?
+    } else {
+        read_bytes_from_file(&options.rom_name)?
+    };
+
+    let rom_hash = hash::sha1(&rom_data);
+    let info = look_up_rom(options, &rom_hash)?;
+    if options.remember {
+        let path = settings::remember(&rom_hash, options.quirks, options.instructions_per_frame)?;
+        eprintln!(
+            "Saved settings for {} to {}",
+            options.rom_name,
+            path.display()
+        );
+    }
+
+    let beeper = audio::setup_audio();
+
+    let quirks = options.quirks.or(info.quirks).unwrap_or_default();
+    let mut chip8 = Chip8::new(beeper, quirks);
+    let seed = options.seed.unwrap_or_else(rand::random);
+    chip8.set_seed(seed);
+
+    chip8.load_rom(&rom_data)?;
+
+    if let Some(trace_file) = &options.trace_file {
+        let file = File::create(trace_file)
+            .map_err(|err| format!("Unable to create trace file {}: {}", trace_file, err))?;
+        let writer = TraceWriter::new(BufWriter::new(file), options.trace_format);
+        chip8.set_tracer(Box::new(writer));
+    }
+
+    let movie = options
+        .record_file
+        .as_ref()
+        .map(|_| Movie::new(&rom_data, seed, quirks, info.palette.unwrap_or_default()));
+
+    Ok((chip8, info, movie))
+}
+
+/// The keymap and gamepad profile for the loaded ROM, from the keymap file if there is one.
+/// The gamepad follows the ROM database's key hints unless the file says otherwise.
+fn load_controls(
+    options: &Options,
+    chip8: &Chip8,
+    info: &RomInfo,
+) -> Result<(Keymap, GamepadProfile), String> {
+    let layout = options.layout.as_deref();
+    let mut profile = GamepadProfile::default();
+    profile.apply_key_hints(&info.keys);
+
+    match &options.keymap_file {
+        Some(keymap_file) => {
+            let text = fs::read_to_string(keymap_file)
+                .map_err(|err| format!("Unable to open file {}: {}", keymap_file, err))?;
+            let rom_hash = hash::to_hex(&chip8.cpu().rom_hash());
+            let invalid = |err| format!("Invalid keymap {}: {}", keymap_file, err);
+            let keymap = Keymap::from_config(&text, layout, &options.rom_name, &rom_hash)
+                .map_err(invalid)?;
+            profile
+                .apply_config(&text, &options.rom_name, &rom_hash)
+                .map_err(invalid)?;
+            Ok((keymap, profile))
+        }
+        None => {
+            let keymap = match layout {
+                Some(name) => {
+                    Keymap::layout(name).ok_or_else(|| format!("Unknown layout: {}", name))?
+                }
+                None => Keymap::default(),
+            };
+            Ok((keymap, profile))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn gamepad_source() -> Box<dyn GamepadSource> {
+    Box::new(joystick::Joysticks::spawn())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn gamepad_source() -> Box<dyn GamepadSource> {
+    Box::new(gamepad::NoGamepads)
+}
+
+fn save_movie(movie: &Movie, record_file: &str) {
+    match fs::write(record_file, movie.to_bytes()) {
+        Ok(()) => eprintln!("Recorded {} frames to {}", movie.frame_count(), record_file),
+        Err(err) => eprintln!("Unable to save movie to {}: {}", record_file, err),
+    }
+}
+
+pub fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    // Anything that doesn't go frame by frame can't be replayed
+    if options.record_file.is_some() && (options.debug || options.wall_clock_timers) {
+        return Err("Recording can't be combined with the debugger or wall clock timers".into());
+    }
+
+    let (mut chip8, info, mut recording) = setup_machine(&options)?;
+    let (keymap, gamepad_profile) = load_controls(&options, &chip8, &info)?;
+    let mut gamepads = Gamepads::new(gamepad_source(), gamepad_profile);
+
+    let event_loop = EventLoop::new();
+    let mut input = WinitInputHelper::new();
+
+    let window = {
+        let size = LogicalSize::new(WINDOW_WIDTH as f64, WINDOW_HEIGHT as f64);
+        WindowBuilder::new()
+            .with_title("Chip-8 Emulator")
+            .with_inner_size(size)
+            .with_min_inner_size(size)
+            .build(&event_loop)?
+    };
+
+    let pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(
+            HIRES_DISPLAY_WIDTH as u32,
+            HIRES_DISPLAY_HEIGHT as u32,
+            surface_texture,
+        )?
+    };
+    let mut screen = Screen {
+        pixels,
+        palette: info.palette.unwrap_or_default(),
+    };
+
+    let console = if options.debug {
+        chip8.pause();
+        Some(console::Console::spawn())
+    } else {
+        None
+    };
+
+    let rewind_seconds = if recording.is_some() {
+        0
+    } else {
+        options.rewind_seconds
+    };
+    let mut rewind = Rewind::new(rewind_seconds);
+    let mut scheduler = Scheduler::new(
+        options
+            .instructions_per_frame
+            .or(info.tickrate)
+            .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME),
+    );
+    let mut next_frame = Instant::now();
+    let mut wall_clock = WallClock::new();
+
+    event_loop.run(move |event, _, control_flow| {
+        if let Event::LoopDestroyed = event {
+            if let Err(err) = chip8.flush_trace() {
+                eprintln!("Unable to write trace: {}", err);
+            }
+            if let (Some(movie), Some(record_file)) = (&recording, &options.record_file) {
+                save_movie(movie, record_file);
+            }
+            return;
+        }
+
+        if let Event::RedrawRequested(_) = event {
+            if screen.pixels.render().is_err() {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+        }
+
+        if input.update(&event) {
+            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+
+            input::handle_input(chip8.keyboard_mut(), &keymap, &gamepads, &input);
+            if recording.is_none() {
+                slots::handle_input(&mut chip8, &options.rom_name, &input);
+            }
+
+            if input.key_pressed(SPEED_UP_KEY) {
+                eprintln!("{} instructions per frame", scheduler.speed_up());
+            }
+            if input.key_pressed(SLOW_DOWN_KEY) {
+                eprintln!("{} instructions per frame", scheduler.slow_down());
+            }
+
+            if let Some(size) = input.window_resized() {
+                screen.pixels.resize(size.width, size.height)
+            }
+        }
+
+        if let Some(console) = &console {
+            console.handle_commands(&mut chip8);
+        }
+
+        // Everything else waits for the next frame, sleeping until it's due
+        let now = Instant::now();
+        if now < next_frame {
+            *control_flow = ControlFlow::WaitUntil(next_frame);
+            return;
+        }
+        next_frame += FRAME_DURATION;
+        if next_frame < now {
+            // Too far behind to catch up, e.g. after the window was dragged
+            next_frame = now + FRAME_DURATION;
+        }
+        *control_flow = ControlFlow::WaitUntil(next_frame);
+
+        if gamepads.update() {
+            input::update_keys(chip8.keyboard_mut(), &keymap, &gamepads, &input);
+        }
+
+        // While the rewind key is held the machine is paused, stepping back one snapshot
+        // per frame
+        if input.key_held(REWIND_KEY) {
+            match rewind.rewind(&mut chip8) {
+                Ok(_) => chip8.draw(&mut screen),
+                Err(err) => eprintln!("Unable to rewind: {}", err),
+            }
+            window.request_redraw();
+            return;
+        }
+
+        if let Some(movie) = &mut recording {
+            movie.record_frame(chip8.keyboard(), &scheduler);
+        }
+
+        // Paused in the debugger nothing runs, so there's no new frame to rewind to
+        let running = !chip8.is_paused();
+        let result = if options.wall_clock_timers {
+            scheduler.run_frame_with_ticks(&mut chip8, &mut screen, wall_clock.ticks())
+        } else {
+            scheduler.run_frame(&mut chip8, &mut screen)
+        };
+
+        // With the debugger attached a fault just pauses, so it can be inspected
+        if let Some(console) = &console {
+            console.report_stop(&mut chip8);
+        } else if let Err(err) = result {
+            eprintln!("Emulation stopped: {}", err);
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+
+        if running {
+            rewind.record_frame(&chip8);
+        }
+
+        if chip8.cpu().is_halted() {
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+
+        window.request_redraw();
+    });
+}
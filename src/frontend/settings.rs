@@ -0,0 +1,118 @@
+use std::{env, fs, path::PathBuf};
+
+use crate::json::Json;
+use crate::romdb::{self, RomDatabase};
+use crate::{hash, Quirks};
+
+/// A copy of the community database's `programs.json`, read if it's in the config directory.
+const PROGRAMS_FILE: &str = "programs.json";
+/// Settings saved with `--remember`, adjusting the database's.
+const OVERRIDES_FILE: &str = "rom-overrides.json";
+
+/// Where settings are kept: `$XDG_CONFIG_HOME/chip8`, `~/.config/chip8` or, on Windows,
+/// `%APPDATA%\chip8`.
+fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("chip8"))
+}
+
+fn read_optional(path: &PathBuf) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Unable to open file {}: {}", path.display(), err)),
+    }
+}
+
+/// The built in database, the one in the config directory, then `extra_file`, then the
+/// saved overrides. ROMs whose entries can't be read are skipped with a warning.
+pub fn load_database(extra_file: Option<&str>) -> Result<RomDatabase, String> {
+    let mut database = RomDatabase::built_in();
+    let invalid = |path: &PathBuf, err| format!("Invalid ROM database {}: {}", path.display(), err);
+    let warn = |path: &PathBuf, warnings: Vec<String>| {
+        for warning in warnings {
+            eprintln!("{}: {}", path.display(), warning);
+        }
+    };
+
+    let config_dir = config_dir();
+    if let Some(path) = config_dir.as_ref().map(|dir| dir.join(PROGRAMS_FILE)) {
+        if let Some(text) = read_optional(&path)? {
+            warn(
+                &path,
+                database.load(&text).map_err(|err| invalid(&path, err))?,
+            );
+        }
+    }
+    if let Some(extra_file) = extra_file {
+        let path = PathBuf::from(extra_file);
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("Unable to open file {}: {}", extra_file, err))?;
+        warn(
+            &path,
+            database.load(&text).map_err(|err| invalid(&path, err))?,
+        );
+    }
+    if let Some(path) = config_dir.as_ref().map(|dir| dir.join(OVERRIDES_FILE)) {
+        if let Some(text) = read_optional(&path)? {
+            let warnings = database
+                .load_overrides(&text)
+                .map_err(|err| invalid(&path, err))?;
+            warn(&path, warnings);
+        }
+    }
+
+    Ok(database)
+}
+
+/// Saves the quirks and speed to use for a ROM from now on, returning where they went.
+pub fn remember(
+    rom_hash: &[u8; 20],
+    quirks: Option<Quirks>,
+    tickrate: Option<usize>,
+) -> Result<PathBuf, String> {
+    let dir = config_dir().ok_or("Unable to find a config directory to save settings in")?;
+    let path = dir.join(OVERRIDES_FILE);
+
+    let mut overrides = match read_optional(&path)? {
+        Some(text) => match Json::parse(&text) {
+            Ok(Json::Object(overrides)) => overrides,
+            _ => return Err(format!("Invalid ROM overrides {}", path.display())),
+        },
+        None => vec![],
+    };
+
+    let hash = hash::to_hex(rom_hash);
+    let index = match overrides.iter().position(|(known, _)| *known == hash) {
+        Some(index) => index,
+        None => {
+            overrides.push((hash, Json::Object(vec![])));
+            overrides.len() - 1
+        }
+    };
+    let entry = &mut overrides[index].1;
+    if entry.as_object().is_none() {
+        *entry = Json::Object(vec![]);
+    }
+    if let Json::Object(settings) = entry {
+        let mut set = |name: &str, value: Json| match settings.iter_mut().find(|(n, _)| n == name) {
+            Some((_, old)) => *old = value,
+            None => settings.push((name.to_string(), value)),
+        };
+        if let Some(quirks) = quirks {
+            set("quirks", romdb::quirks_to_json(&quirks));
+        }
+        if let Some(tickrate) = tickrate {
+            set("tickrate", Json::Number(tickrate as f64));
+        }
+    }
+
+    fs::create_dir_all(&dir)
+        .map_err(|err| format!("Unable to create {}: {}", dir.display(), err))?;
+    fs::write(&path, Json::Object(overrides).pretty() + "\n")
+        .map_err(|err| format!("Unable to save {}: {}", path.display(), err))?;
+    Ok(path)
+}
@@ -0,0 +1,50 @@
+use std::{fs, path::PathBuf};
+
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::Chip8;
+
+/// F1 to F4 load the numbered save slots; with shift held they save to them instead.
+const SLOT_KEYS: [VirtualKeyCode; 4] = [
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+];
+
+/// Slots are kept next to the ROM, e.g. `pong.ch8.state1`.
+fn slot_path(rom_name: &str, slot: usize) -> PathBuf {
+    PathBuf::from(format!("{}.state{}", rom_name, slot))
+}
+
+fn save_slot(chip8: &Chip8, rom_name: &str, slot: usize) {
+    let path = slot_path(rom_name, slot);
+    match fs::write(&path, chip8.save_state()) {
+        Ok(()) => eprintln!("Saved state to {}", path.display()),
+        Err(err) => eprintln!("Unable to save state to {}: {}", path.display(), err),
+    }
+}
+
+fn load_slot(chip8: &mut Chip8, rom_name: &str, slot: usize) {
+    let path = slot_path(rom_name, slot);
+    let result = fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|data| chip8.load_state(&data).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => eprintln!("Loaded state from {}", path.display()),
+        Err(err) => eprintln!("Unable to load state from {}: {}", path.display(), err),
+    }
+}
+
+pub fn handle_input(chip8: &mut Chip8, rom_name: &str, input: &WinitInputHelper) {
+    for (i, &key) in SLOT_KEYS.iter().enumerate() {
+        if input.key_pressed(key) {
+            if input.held_shift() {
+                save_slot(chip8, rom_name, i + 1);
+            } else {
+                load_slot(chip8, rom_name, i + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{cell::RefCell, rc::Rc};
+
+/// Something that can play the CHIP-8 buzzer tone.
+pub trait AudioSink {
+    fn start_tone(&mut self);
+    fn stop_tone(&mut self);
+
+    /// Replaces the tone with an XO-CHIP 1-bit waveform, played back at
+    /// `4000 * 2^((pitch - 64) / 48)` bits per second.
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+}
+
+/// An audio sink that discards everything, for running without a sound device.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn start_tone(&mut self) {}
+
+    fn stop_tone(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneEvent {
+    Start,
+    Stop,
+    Pattern([u8; 16], u8),
+}
+
+/// An audio sink that keeps a log of every tone change.
+///
+/// Clones share the same log, so keep one around before handing the other to the `CPU`.
+#[derive(Clone, Default)]
+pub struct RecordingAudio {
+    events: Rc<RefCell<Vec<ToneEvent>>>,
+}
+
+impl RecordingAudio {
+    pub fn new() -> Self {
+        RecordingAudio::default()
+    }
+
+    pub fn events(&self) -> Vec<ToneEvent> {
+        self.events.borrow().clone()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        let events = self.events.borrow();
+        let last_change = events
+            .iter()
+            .rev()
+            .find(|event| !matches!(event, ToneEvent::Pattern(..)));
+        last_change == Some(&ToneEvent::Start)
+    }
+}
+
+impl AudioSink for RecordingAudio {
+    fn start_tone(&mut self) {
+        self.events.borrow_mut().push(ToneEvent::Start);
+    }
+
+    fn stop_tone(&mut self) {
+        self.events.borrow_mut().push(ToneEvent::Stop);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.events
+            .borrow_mut()
+            .push(ToneEvent::Pattern(*pattern, pitch));
+    }
+}
@@ -1,35 +1,87 @@
-use std::time::{Duration, Instant};
-
+use super::audio::AudioSink;
+use super::display::Framebuffer;
+use super::font::{BIG_FONT, FONT};
 use super::instruction::Instruction;
-use super::{font::FONT, Keyboard};
+use super::quirks::Quirks;
+use super::random::RandomGenerator;
+use super::state::{StateReader, StateWriter};
+use super::Keyboard;
+use crate::{hash, EmulatorError};
+
+const PROGRAM_START: usize = 0x200;
+const STACK_SIZE: usize = 16;
+const MEMORY_SIZE: usize = 0x1000;
+/// XO-CHIP's memory, for machines with the `extended_memory` quirk.
+const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+const DEFAULT_PITCH: u8 = 64;
+const FONT_START: usize = 0x0;
+const BIG_FONT_START: usize = FONT_START + FONT.len();
+
+const SAVE_STATE_MAGIC: &[u8; 4] = b"C8ST";
+const SAVE_STATE_VERSION: u16 = 4;
+/// Save states from before the random number generator was stored in them.
+const SAVE_STATE_VERSION_WITHOUT_RANDOM: u16 = 1;
+/// Save states from before `FX0A`'s key and the display wait were stored in them.
+const SAVE_STATE_VERSION_WITHOUT_WAITS: u16 = 2;
+/// Save states from before the size of memory depended on the platform, which always hold
+/// XO-CHIP's 64 KiB.
+const SAVE_STATE_VERSION_WITHOUT_MEMORY_SIZE: u16 = 3;
 
-use rand::Rng;
-use rodio::Sink;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
 
-const HZ_60: f64 = 1.0 / 60.0;
-pub const DISPLAY_HEIGHT: usize = 32;
-pub const DISPLAY_WIDTH: usize = 64;
+/// A read or write of memory by an instruction, as opposed to fetching the instruction itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub kind: AccessKind,
+    pub value: u8,
+}
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     program_counter: u16,
-    memory: [u8; 4096],
-    display: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    memory: Vec<u8>,
+    display: Framebuffer,
     registers: [u8; 16],
     address_register: u16,
     stack: Vec<u16>,
     delay_timer: u8,
     sound_timer: u8,
-    beeper: Sink,
-    time_when_updated: Instant,
+    beeper: Box<dyn AudioSink>,
+    rpl_flags: [u8; 16],
+    planes: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    halted: bool,
+    vblank: bool,
+    /// The key `FX0A` saw go down, and is waiting to be released.
+    awaited_key: Option<u8>,
+    /// The key that last answered `FX0A` this frame, so the same press isn't taken twice.
+    answered_key: Option<u8>,
+    quirks: Quirks,
+    random: RandomGenerator,
+    rom_hash: [u8; 20],
+    track_accesses: bool,
+    accesses: Vec<MemoryAccess>,
 }
 
 impl CPU {
-    pub fn new(beeper: Sink) -> CPU {
-        let program_counter = 0x200;
+    pub fn new(beeper: Box<dyn AudioSink>, quirks: Quirks) -> CPU {
+        let program_counter = PROGRAM_START as u16;
 
-        let mut memory = [0; 4096];
+        let memory_size = if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        };
+        let mut memory = vec![0; memory_size];
 
-        memory[0x0..0x50].copy_from_slice(&FONT);
+        memory[FONT_START..BIG_FONT_START].copy_from_slice(&FONT);
+        memory[BIG_FONT_START..(BIG_FONT_START + BIG_FONT.len())].copy_from_slice(&BIG_FONT);
 
         let registers = [0; 16];
         let stack = vec![];
@@ -38,68 +90,256 @@ impl CPU {
         CPU {
             program_counter,
             memory,
-            display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            display: Framebuffer::new(),
             registers,
             address_register,
             stack,
             delay_timer: 0,
             sound_timer: 0,
             beeper,
-            time_when_updated: Instant::now(),
+            rpl_flags: [0; 16],
+            planes: 1,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            halted: false,
+            vblank: false,
+            awaited_key: None,
+            answered_key: None,
+            quirks,
+            random: RandomGenerator::new(0),
+            rom_hash: hash::sha1(&[]),
+            track_accesses: false,
+            accesses: vec![],
         }
     }
 
-    fn flip_pixel(&mut self, sprite_bit: bool, x: u16, y: u16) -> bool {
-        let y = y as usize % DISPLAY_HEIGHT;
-        let x = x as usize % DISPLAY_WIDTH;
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
 
-        let pixel = self.display[y][x];
+    /// Restarts the numbers `CXNN` produces from `seed`, so a run can be repeated. A new
+    /// `CPU` starts from a seed of 0.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = RandomGenerator::new(seed);
+    }
 
-        self.display[y][x] = sprite_bit != pixel;
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
 
-        pixel && !self.display[y][x]
+    /// Whether the program has stopped itself with `00FD`.
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    fn get_sprite_bit(&self, x: u8, y: u8) -> bool {
-        let index = self.address_register + y as u16;
-        let row = self.memory[index as usize];
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
 
-        row.reverse_bits() & (1 << x) != 0
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
     }
 
-    fn execute(&mut self, instr: Instruction, keyboard: &Keyboard) {
-        match instr {
-            Instruction::Clear => {
-                for row in self.display.iter_mut() {
-                    for p in row.iter_mut() {
-                        *p = false;
-                    }
-                }
+    pub fn address_register(&self) -> u16 {
+        self.address_register
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The memory reads and writes made by the last instruction, if tracking is enabled.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    /// Turns recording of `memory_accesses` on or off. It's only needed for watchpoints.
+    pub(crate) fn set_track_accesses(&mut self, track: bool) {
+        self.track_accesses = track;
+        if !track {
+            self.accesses.clear();
+        }
+    }
+
+    /// Decodes the instruction at the program counter without executing it.
+    pub fn next_instruction(&self) -> Option<Instruction> {
+        let pc = self.program_counter;
+        let opcode = self.read_word(pc as usize, pc).ok()?;
+        if Instruction::is_long(opcode) {
+            let operand = self.read_word(pc as usize + 2, pc).ok()?;
+            Instruction::decode_long(opcode, operand)
+        } else {
+            Instruction::decode(opcode)
+        }
+    }
+
+    fn flip_pixel(&mut self, plane: u8, sprite_bit: bool, x: usize, y: usize) -> bool {
+        let (width, height) = (self.display.width(), self.display.height());
+        if self.quirks.clip_sprites && (x >= width || y >= height) {
+            return false;
+        }
+
+        self.display.flip(plane, sprite_bit, x % width, y % height)
+    }
+
+    fn read_memory(&self, addr: usize, pc: u16) -> Result<u8, EmulatorError> {
+        self.memory
+            .get(addr)
+            .copied()
+            .ok_or(EmulatorError::MemoryOutOfBounds { addr, pc })
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u8, pc: u16) -> Result<(), EmulatorError> {
+        match self.memory.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                self.record_access(addr, AccessKind::Write, value);
+                Ok(())
             }
+            None => Err(EmulatorError::MemoryOutOfBounds { addr, pc }),
+        }
+    }
+
+    /// Reads memory on behalf of an instruction, recording the access.
+    fn load_memory(&mut self, addr: usize, pc: u16) -> Result<u8, EmulatorError> {
+        let value = self.read_memory(addr, pc)?;
+        self.record_access(addr, AccessKind::Read, value);
+        Ok(value)
+    }
+
+    fn record_access(&mut self, address: usize, kind: AccessKind, value: u8) {
+        let access = MemoryAccess {
+            address,
+            kind,
+            value,
+        };
+        // Sprites are read a bit at a time, so skip repeats of the same byte
+        if self.track_accesses && self.accesses.last() != Some(&access) {
+            self.accesses.push(access);
+        }
+    }
+
+    fn read_word(&self, addr: usize, pc: u16) -> Result<u16, EmulatorError> {
+        let high = self.read_memory(addr, pc)?;
+        let low = self.read_memory(addr + 1, pc)?;
+
+        Ok(((high as u16) << 8) | (low as u16))
+    }
+
+    fn get_sprite_bit(
+        &mut self,
+        sprite: usize,
+        x: u8,
+        y: u8,
+        width: u8,
+        pc: u16,
+    ) -> Result<bool, EmulatorError> {
+        let bytes_per_row = width as usize / 8;
+        let index = sprite + y as usize * bytes_per_row + x as usize / 8;
+        let row = self.load_memory(index, pc)?;
+
+        Ok(row.reverse_bits() & (1 << (x % 8)) != 0)
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            // Skipping an XO-CHIP long instruction has to jump over both of its words
+            let next = self.program_counter as usize;
+            let is_long = match (self.memory.get(next), self.memory.get(next + 1)) {
+                (Some(&high), Some(&low)) => {
+                    Instruction::is_long(((high as u16) << 8) | low as u16)
+                }
+                _ => false,
+            };
+            let length = if is_long { 4 } else { 2 };
+            self.program_counter = self.program_counter.wrapping_add(length);
+        }
+    }
+
+    fn reset_flag(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[15] = 0;
+        }
+    }
+
+    fn shift_source(&self, reg: usize, other_reg: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[other_reg]
+        } else {
+            self.registers[reg]
+        }
+    }
+
+    fn increment_after_load_store(&mut self, reg: usize) {
+        if self.quirks.load_store_increments_i {
+            let count = if self.quirks.load_store_increments_i_by_x {
+                reg
+            } else {
+                reg + 1
+            };
+            self.address_register = self.address_register.wrapping_add(count as u16);
+        }
+    }
+
+    fn execute(
+        &mut self,
+        instr: Instruction,
+        pc: u16,
+        keyboard: &Keyboard,
+    ) -> Result<(), EmulatorError> {
+        match instr {
+            Instruction::Clear => self.display.clear(self.planes),
             Instruction::Return => {
                 self.program_counter = match self.stack.pop() {
                     Some(address) => address,
-                    None => panic!("Tried to pop empty stack"),
+                    None => return Err(EmulatorError::StackUnderflow { pc }),
                 }
             }
+            Instruction::ScrollDown(rows) => self.display.scroll_down(self.planes, rows as usize),
+            Instruction::ScrollUp(rows) => self.display.scroll_up(self.planes, rows as usize),
+            Instruction::ScrollRight => self.display.scroll_right(self.planes, 4),
+            Instruction::ScrollLeft => self.display.scroll_left(self.planes, 4),
+            Instruction::Exit => {
+                self.halted = true;
+                self.program_counter = pc;
+            }
+            Instruction::LowRes => self.display.set_hires(false),
+            Instruction::HighRes => self.display.set_hires(true),
             Instruction::Jump(address) => self.program_counter = address,
             Instruction::Call(address) => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(EmulatorError::StackOverflow { pc });
+                }
                 self.stack.push(self.program_counter);
                 self.program_counter = address;
             }
-            Instruction::RegEq(reg, value) => {
-                if self.registers[reg] == value {
-                    self.program_counter += 2;
-                }
+            Instruction::RegEq(reg, value) => self.skip_if(self.registers[reg] == value),
+            Instruction::RegNeq(reg, value) => self.skip_if(self.registers[reg] != value),
+            Instruction::RegEqReg(reg, other_reg) => {
+                self.skip_if(self.registers[reg] == self.registers[other_reg])
             }
-            Instruction::RegNeq(reg, value) => {
-                if self.registers[reg] != value {
-                    self.program_counter += 2;
+            Instruction::SaveRange(reg, other_reg) => {
+                for (offset, i) in register_range(reg, other_reg).enumerate() {
+                    let addr = self.address_register as usize + offset;
+                    self.write_memory(addr, self.registers[i], pc)?;
                 }
             }
-            Instruction::RegEqReg(reg, other_reg) => {
-                if self.registers[reg] == self.registers[other_reg] {
-                    self.program_counter += 2;
+            Instruction::LoadRange(reg, other_reg) => {
+                for (offset, i) in register_range(reg, other_reg).enumerate() {
+                    let addr = self.address_register as usize + offset;
+                    self.registers[i] = self.load_memory(addr, pc)?;
                 }
             }
             Instruction::SetReg(reg, value) => {
@@ -111,9 +351,18 @@ impl CPU {
             Instruction::RegSetReg(reg, other_reg) => {
                 self.registers[reg] = self.registers[other_reg]
             }
-            Instruction::Or(reg, other_reg) => self.registers[reg] |= self.registers[other_reg],
-            Instruction::And(reg, other_reg) => self.registers[reg] &= self.registers[other_reg],
-            Instruction::XOr(reg, other_reg) => self.registers[reg] ^= self.registers[other_reg],
+            Instruction::Or(reg, other_reg) => {
+                self.registers[reg] |= self.registers[other_reg];
+                self.reset_flag();
+            }
+            Instruction::And(reg, other_reg) => {
+                self.registers[reg] &= self.registers[other_reg];
+                self.reset_flag();
+            }
+            Instruction::XOr(reg, other_reg) => {
+                self.registers[reg] ^= self.registers[other_reg];
+                self.reset_flag();
+            }
             Instruction::Add(reg, other_reg) => {
                 let old_value = self.registers[reg];
                 self.registers[reg] = self.registers[reg].wrapping_add(self.registers[other_reg]);
@@ -128,158 +377,1532 @@ impl CPU {
                 self.registers[reg] = self.registers[reg].wrapping_sub(self.registers[other_reg]);
                 self.registers[15] = if will_borrow { 0 } else { 1 };
             }
-            Instruction::ShiftR(reg) => {
-                let lsb = self.registers[reg] & 0x01;
-                self.registers[reg] >>= 1;
-                self.registers[15] = lsb;
+            Instruction::ShiftR(reg, other_reg) => {
+                let value = self.shift_source(reg, other_reg);
+                self.registers[reg] = value >> 1;
+                self.registers[15] = value & 0x01;
             }
             Instruction::RevSub(reg, other_reg) => {
                 let will_borrow = self.registers[other_reg] < self.registers[reg];
                 self.registers[reg] = self.registers[other_reg].wrapping_sub(self.registers[reg]);
                 self.registers[15] = if will_borrow { 0 } else { 1 };
             }
-            Instruction::ShiftL(reg) => {
-                let msb = (self.registers[reg] & 0x80) >> 7;
-                self.registers[reg] <<= 1;
-                self.registers[15] = msb;
+            Instruction::ShiftL(reg, other_reg) => {
+                let value = self.shift_source(reg, other_reg);
+                self.registers[reg] = value << 1;
+                self.registers[15] = (value & 0x80) >> 7;
             }
             Instruction::RegNeqReg(reg, other_reg) => {
-                if self.registers[reg] != self.registers[other_reg] {
-                    self.program_counter += 2;
-                }
+                self.skip_if(self.registers[reg] != self.registers[other_reg])
             }
-            Instruction::SetAddress(address) => {
+            Instruction::SetAddress(address) | Instruction::LongSetAddress(address) => {
                 self.address_register = address;
             }
             Instruction::JumpOffset(address) => {
-                self.program_counter = (address + self.registers[0] as u16) % 4096
+                let reg = if self.quirks.jump_uses_vx {
+                    (address >> 8) as usize
+                } else {
+                    0
+                };
+                self.program_counter = (address + self.registers[reg] as u16) % 4096
             }
             Instruction::Random(reg, value) => {
-                let mut rng = rand::thread_rng();
-                let random_value = rng.gen::<u8>();
+                let random_value = if self.quirks.memory_random {
+                    self.random.next_memory_byte(&self.memory)
+                } else {
+                    self.random.next_byte()
+                };
                 self.registers[reg] = random_value & value;
             }
             Instruction::Draw(x, y, height) => {
+                if self.quirks.display_wait && !self.vblank {
+                    self.program_counter = pc;
+                    return Ok(());
+                }
+                self.vblank = false;
+
+                // The starting position always wraps, even when the sprite itself is clipped
+                let origin_x = self.registers[x] as usize % self.display.width();
+                let origin_y = self.registers[y] as usize % self.display.height();
+
+                // A height of zero draws a 16x16 SUPER-CHIP sprite
+                let (width, height) = if height == 0 { (16, 16) } else { (8, height) };
+
+                // With both XO-CHIP planes selected the sprite data for the second plane
+                // follows straight after the first
+                let mut sprite = self.address_register as usize;
                 let mut did_change = false;
-                for sprite_y in 0..height {
-                    for sprite_x in 0..8 {
-                        let sprite_bit = self.get_sprite_bit(sprite_x, sprite_y);
-                        did_change = self.flip_pixel(
-                            sprite_bit,
-                            self.registers[x] as u16 + sprite_x as u16,
-                            self.registers[y] as u16 + sprite_y as u16,
-                        ) || did_change;
+                let planes = self.planes;
+                for plane in [1, 2].iter().filter(|&&plane| planes & plane != 0) {
+                    for sprite_y in 0..height {
+                        for sprite_x in 0..width {
+                            let sprite_bit =
+                                self.get_sprite_bit(sprite, sprite_x, sprite_y, width, pc)?;
+                            did_change = self.flip_pixel(
+                                *plane,
+                                sprite_bit,
+                                origin_x + sprite_x as usize,
+                                origin_y + sprite_y as usize,
+                            ) || did_change;
+                        }
                     }
+                    sprite += width as usize / 8 * height as usize;
                 }
                 self.registers[15] = if did_change { 1 } else { 0 };
             }
-            Instruction::KeyEq(reg) => {
-                if keyboard.get_key(self.registers[reg]) {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::KeyNeq(reg) => {
-                if !keyboard.get_key(self.registers[reg]) {
-                    self.program_counter += 2;
-                }
-            }
+            Instruction::KeyEq(reg) => self.skip_if(keyboard.get_key(self.registers[reg])),
+            Instruction::KeyNeq(reg) => self.skip_if(!keyboard.get_key(self.registers[reg])),
             Instruction::GetDelay(reg) => {
                 self.registers[reg] = self.delay_timer;
             }
-            Instruction::WaitKey(reg) => {
-                let key = keyboard.any_key_pressed();
-                match key {
+            Instruction::WaitKey(reg) if self.quirks.wait_key_on_press => {
+                match keyboard.any_key_pressed() {
                     Some(k) => self.registers[reg] = k,
-                    None => self.program_counter -= 2,
+                    None => self.program_counter = pc,
                 };
             }
+            Instruction::WaitKey(reg) => {
+                // Like the COSMAC VIP, wait for a key to go down and come back up, so a key
+                // still held from an earlier prompt isn't taken as the answer. A key tapped
+                // since the frame started counts even if it's already been let go.
+                let answered = self.answered_key;
+                let key = self
+                    .awaited_key
+                    .or_else(|| keyboard.newly_pressed().find(|&key| Some(key) != answered));
+                match key {
+                    Some(key) if !keyboard.get_key(key) => {
+                        self.registers[reg] = key;
+                        self.awaited_key = None;
+                        self.answered_key = Some(key);
+                    }
+                    _ => {
+                        self.awaited_key = key;
+                        self.program_counter = pc;
+                    }
+                }
+            }
             Instruction::SetDelay(reg) => {
                 self.delay_timer = self.registers[reg];
-                self.time_when_updated = Instant::now();
             }
             Instruction::SetSound(reg) => {
                 self.sound_timer = self.registers[reg];
-                self.time_when_updated = Instant::now();
-                self.beeper.play();
+                if self.sound_timer > 0 {
+                    self.beeper.start_tone();
+                } else {
+                    self.beeper.stop_tone();
+                }
             }
             Instruction::IncAddress(reg) => {
-                self.address_register += self.registers[reg] as u16;
+                self.address_register = self
+                    .address_register
+                    .wrapping_add(self.registers[reg] as u16);
             }
             Instruction::SpriteAddress(reg) => {
-                self.address_register = self.registers[reg] as u16 * 5;
+                self.address_register = (FONT_START + self.registers[reg] as usize * 5) as u16;
+            }
+            Instruction::BigSpriteAddress(reg) => {
+                self.address_register = (BIG_FONT_START + self.registers[reg] as usize * 10) as u16;
+            }
+            Instruction::SaveFlags(reg) => {
+                self.rpl_flags[..=reg].copy_from_slice(&self.registers[..=reg]);
+            }
+            Instruction::LoadFlags(reg) => {
+                self.registers[..=reg].copy_from_slice(&self.rpl_flags[..=reg]);
+            }
+            Instruction::SelectPlanes(planes) => self.planes = planes & 0x3,
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; 16];
+                for (i, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.load_memory(self.address_register as usize + i, pc)?;
+                }
+                self.audio_pattern = Some(pattern);
+                self.beeper.set_pattern(&pattern, self.pitch);
+            }
+            Instruction::SetPitch(reg) => {
+                self.pitch = self.registers[reg];
+                if let Some(pattern) = self.audio_pattern {
+                    self.beeper.set_pattern(&pattern, self.pitch);
+                }
             }
             Instruction::RegDump(reg) => {
                 for i in 0..=reg {
-                    self.memory[(self.address_register + i as u16) as usize] = self.registers[i];
+                    let addr = self.address_register as usize + i;
+                    self.write_memory(addr, self.registers[i], pc)?;
                 }
+                self.increment_after_load_store(reg);
             }
             Instruction::RegLoad(reg) => {
                 for i in 0..=reg {
-                    self.registers[i] = self.memory[(self.address_register + i as u16) as usize];
+                    let addr = self.address_register as usize + i;
+                    self.registers[i] = self.load_memory(addr, pc)?;
                 }
+                self.increment_after_load_store(reg);
             }
             Instruction::BCD(reg) => {
+                let addr = self.address_register as usize;
                 let val = self.registers[reg];
-                self.memory[(self.address_register + 2) as usize] = val % 10;
+                self.write_memory(addr + 2, val % 10, pc)?;
 
                 let val = val / 10;
-                self.memory[(self.address_register + 1) as usize] = val % 10;
+                self.write_memory(addr + 1, val % 10, pc)?;
 
                 let val = val / 10;
-                self.memory[self.address_register as usize] = val % 10;
+                self.write_memory(addr, val % 10, pc)?;
             }
-
-            _ => (),
+            Instruction::SysCall(_) => (),
         };
+
+        Ok(())
     }
 
-    fn update_timers(&mut self) {
-        let mut time_since_update = self.time_when_updated.elapsed().as_secs_f64();
-        while time_since_update > HZ_60 {
-            time_since_update -= HZ_60;
-            self.delay_timer = self.delay_timer.saturating_sub(1);
-            self.sound_timer = self.sound_timer.saturating_sub(1);
+    /// Counts the delay and sound timers down by one 60Hz tick, and marks the start of a new
+    /// frame for `display_wait`.
+    ///
+    /// The timers only move when this is called, never with the wall clock, so they count
+    /// down the same way on every run. `Scheduler` calls it once per emulated frame.
+    pub fn tick_frame(&mut self) {
+        let was_sounding = self.sound_timer > 0;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.vblank = true;
+        self.answered_key = None;
 
-            if self.sound_timer == 0 {
-                self.beeper.pause();
-            }
+        if was_sounding && self.sound_timer == 0 {
+            self.beeper.stop_tone();
+        }
+    }
+
+    /// Runs one instruction. The screen isn't presented and the timers don't move; see
+    /// `tick_frame`.
+    pub fn step(&mut self, keyboard: &Keyboard) -> Result<(), EmulatorError> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let pc = self.program_counter;
+        self.accesses.clear();
+
+        // Extract 16 bit instruction code
+        let raw_instr = self.read_word(pc as usize, pc)?;
+
+        // Increment program counter
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        // Decode instruction to enum, fetching the second word of long instructions
+        let decoded_instr = if Instruction::is_long(raw_instr) {
+            let operand = self.read_word(pc as usize + 2, pc)?;
+            self.program_counter = self.program_counter.wrapping_add(2);
+            Instruction::decode_long(raw_instr, operand)
+        } else {
+            Instruction::decode(raw_instr)
+        }
+        .ok_or(EmulatorError::UnknownOpcode {
+            opcode: raw_instr,
+            pc,
+        });
+
+        // Execute instruction, leaving the program counter on it if it faults
+        if let Err(err) = decoded_instr.and_then(|instr| self.execute(instr, pc, keyboard)) {
+            self.program_counter = pc;
+            return Err(err);
         }
 
-        self.time_when_updated = Instant::now() - Duration::from_secs_f64(time_since_update);
+        Ok(())
     }
 
-    fn draw_display(&self, display: &mut [u8]) {
-        for (y, row) in self.display.iter().enumerate() {
-            for (x, pixel) in row.iter().enumerate() {
-                for i in 0..4 {
-                    let index = 4 * (y * DISPLAY_WIDTH + x) + i;
-                    display[index] = if *pixel { 255 } else { 0 };
-                }
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let max = self.memory.len() - PROGRAM_START;
+        if data.len() > max {
+            return Err(EmulatorError::RomTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+
+        self.memory[PROGRAM_START..(PROGRAM_START + data.len())].copy_from_slice(data);
+        self.rom_hash = hash::sha1(data);
+
+        Ok(())
+    }
+
+    /// The SHA-1 of the loaded ROM.
+    pub fn rom_hash(&self) -> [u8; 20] {
+        self.rom_hash
+    }
+
+    /// Snapshots the whole machine: memory, registers, stack, timers, random number generator,
+    /// display, and how far `FX0A` and the display wait have got.
+    ///
+    /// The state starts with a magic number, a format version and the hash of the loaded ROM,
+    /// so it can only be restored into a machine running the same program.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+
+        state.bytes(SAVE_STATE_MAGIC);
+        state.u16(SAVE_STATE_VERSION);
+        state.bytes(&self.rom_hash);
+
+        state.u16(self.program_counter);
+        state.u16(self.address_register);
+        state.bytes(&self.registers);
+        state.u8(self.stack.len() as u8);
+        for &address in self.stack.iter() {
+            state.u16(address);
+        }
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        state.bytes(&self.rpl_flags);
+        state.u8(self.planes);
+        state.u8(self.audio_pattern.is_some() as u8);
+        state.bytes(&self.audio_pattern.unwrap_or_default());
+        state.u8(self.pitch);
+        state.u8(self.halted as u8);
+        self.random.save(&mut state);
+        state.u8(self.vblank as u8);
+        for key in [self.awaited_key, self.answered_key].iter() {
+            state.u8(key.is_some() as u8);
+            state.u8(key.unwrap_or_default());
+        }
+        state.u8(self.display.is_hires() as u8);
+        state.bytes(self.display.pixels());
+        state.bytes(&(self.memory.len() as u32).to_le_bytes());
+        state.bytes(&self.memory);
+
+        state.finish()
+    }
+
+    /// Restores a snapshot taken by `save_state`. Nothing is changed if the state is invalid.
+    ///
+    /// States from before the random number generator was saved are still accepted, and
+    /// leave it as it is. Ones from before the waits were saved start them afresh. A state
+    /// only loads into a machine with the same size of memory, though older ones that always
+    /// held 64 KiB load into a smaller memory if nothing was stored past its end.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let mut state = StateReader::new(data);
+
+        if state.bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(EmulatorError::InvalidSaveState("not a save state"));
+        }
+        let version = state.u16()?;
+        let known = [
+            SAVE_STATE_VERSION,
+            SAVE_STATE_VERSION_WITHOUT_MEMORY_SIZE,
+            SAVE_STATE_VERSION_WITHOUT_WAITS,
+            SAVE_STATE_VERSION_WITHOUT_RANDOM,
+        ];
+        if !known.contains(&version) {
+            return Err(EmulatorError::UnsupportedSaveStateVersion(version));
+        }
+        if state.array::<20>()? != self.rom_hash {
+            return Err(EmulatorError::SaveStateRomMismatch);
+        }
+
+        let program_counter = state.u16()?;
+        let address_register = state.u16()?;
+        let registers = state.array::<16>()?;
+        let stack_len = state.u8()? as usize;
+        if stack_len > STACK_SIZE {
+            return Err(EmulatorError::InvalidSaveState("stack is too deep"));
+        }
+        let stack = (0..stack_len)
+            .map(|_| state.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        let delay_timer = state.u8()?;
+        let sound_timer = state.u8()?;
+        let rpl_flags = state.array::<16>()?;
+        let planes = state.u8()?;
+        let has_pattern = state.bool()?;
+        let pattern = state.array::<16>()?;
+        let pitch = state.u8()?;
+        let halted = state.bool()?;
+        let random = if version == SAVE_STATE_VERSION_WITHOUT_RANDOM {
+            self.random.clone()
+        } else {
+            RandomGenerator::load(&mut state)?
+        };
+        let (vblank, awaited_key, answered_key) =
+            if version < SAVE_STATE_VERSION_WITHOUT_MEMORY_SIZE {
+                (false, None, None)
+            } else {
+                let vblank = state.bool()?;
+                let mut key = || -> Result<Option<u8>, EmulatorError> {
+                    let (is_some, key) = (state.bool()?, state.u8()?);
+                    if key > 0xF {
+                        return Err(EmulatorError::InvalidSaveState("key is out of range"));
+                    }
+                    Ok(if is_some { Some(key) } else { None })
+                };
+                (vblank, key()?, key()?)
+            };
+        let mut display = Framebuffer::new();
+        display.set_hires(state.bool()?);
+        let pixels = state.bytes(display.pixels().len())?;
+        let memory = if version < SAVE_STATE_VERSION {
+            let memory = state.bytes(EXTENDED_MEMORY_SIZE)?;
+            let (memory, rest) = memory.split_at(self.memory.len().min(memory.len()));
+            if rest.iter().any(|&byte| byte != 0) {
+                return Err(EmulatorError::InvalidSaveState(
+                    "memory is a different size",
+                ));
+            }
+            memory
+        } else {
+            let memory_size = u32::from_le_bytes(state.array()?) as usize;
+            if memory_size != self.memory.len() {
+                return Err(EmulatorError::InvalidSaveState(
+                    "memory is a different size",
+                ));
             }
+            state.bytes(memory_size)?
+        };
+        if !state.is_empty() {
+            return Err(EmulatorError::InvalidSaveState(
+                "unexpected data at the end",
+            ));
         }
+        display.restore(pixels);
+
+        self.program_counter = program_counter;
+        self.address_register = address_register;
+        self.registers = registers;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.rpl_flags = rpl_flags;
+        self.planes = planes;
+        self.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = pitch;
+        self.halted = halted;
+        self.random = random;
+        self.display = display;
+        self.memory.copy_from_slice(memory);
+        self.vblank = vblank;
+        self.awaited_key = awaited_key;
+        self.answered_key = answered_key;
+
+        if let Some(pattern) = self.audio_pattern {
+            self.beeper.set_pattern(&pattern, self.pitch);
+        }
+        if self.sound_timer > 0 {
+            self.beeper.start_tone();
+        } else {
+            self.beeper.stop_tone();
+        }
+
+        Ok(())
     }
+}
 
-    pub fn step(&mut self, display: &mut [u8], keyboard: &Keyboard) {
-        // Extract 16 bit instruction code
-        let raw_instr_high = self.memory[self.program_counter as usize];
-        let raw_instr_low = self.memory[(self.program_counter + 1) as usize];
-        let raw_instr = ((raw_instr_high as u16) << 8) | (raw_instr_low as u16);
+/// The registers from `from` to `to` inclusive, counting down if `to` is the lower of the two.
+fn register_range(from: usize, to: usize) -> Box<dyn Iterator<Item = usize>> {
+    if from <= to {
+        Box::new(from..=to)
+    } else {
+        Box::new((to..=from).rev())
+    }
+}
 
-        // Increment program counter
-        self.program_counter += 2;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{NullAudio, RecordingAudio, ToneEvent};
+
+    fn machine(quirks: Quirks) -> CPU {
+        CPU::new(Box::new(NullAudio), quirks)
+    }
+
+    /// Writes the instruction at the program counter and runs it.
+    fn try_exec(
+        cpu: &mut CPU,
+        instr: Instruction,
+        keyboard: &Keyboard,
+    ) -> Result<(), EmulatorError> {
+        let pc = cpu.program_counter as usize;
+        let bytes = instr.encode();
+        cpu.memory[pc..pc + bytes.len()].copy_from_slice(&bytes);
+        cpu.step(keyboard)
+    }
+
+    fn exec(cpu: &mut CPU, instr: Instruction) {
+        try_exec(cpu, instr, &Keyboard::new()).unwrap();
+    }
+
+    fn exec_with_keys(cpu: &mut CPU, instr: Instruction, keys: &[u8]) {
+        let mut keyboard = Keyboard::new();
+        for &key in keys {
+            keyboard.set_key(key, true);
+        }
+        try_exec(cpu, instr, &keyboard).unwrap();
+    }
+
+    /// Runs `instr` with the two registers set, returning the result and VF.
+    fn alu(quirks: Quirks, instr: Instruction, x: u8, y: u8) -> (u8, u8) {
+        let mut cpu = machine(quirks);
+        cpu.registers[1] = x;
+        cpu.registers[2] = y;
+        exec(&mut cpu, instr);
+        (cpu.registers[1], cpu.registers[15])
+    }
+
+    fn lit_pixels(cpu: &CPU) -> Vec<(usize, usize)> {
+        let display = &cpu.display;
+        (0..display.height())
+            .flat_map(|y| (0..display.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| display.get_pixel(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn clear_only_clears_selected_planes() {
+        let mut cpu = machine(Quirks::default());
+        cpu.display.flip(1, true, 3, 4);
+        cpu.display.flip(2, true, 3, 4);
+
+        exec(&mut cpu, Instruction::SelectPlanes(2));
+        exec(&mut cpu, Instruction::Clear);
+        assert_eq!(cpu.display.get_color(3, 4), 1);
+
+        exec(&mut cpu, Instruction::SelectPlanes(1));
+        exec(&mut cpu, Instruction::Clear);
+        assert!(lit_pixels(&cpu).is_empty());
+    }
+
+    #[test]
+    fn call_and_return() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::Call(0x300));
+        assert_eq!(cpu.program_counter, 0x300);
+        assert_eq!(cpu.stack, vec![0x202]);
+
+        exec(&mut cpu, Instruction::Return);
+        assert_eq!(cpu.program_counter, 0x202);
+        assert!(cpu.stack.is_empty());
+    }
+
+    #[test]
+    fn return_with_empty_stack_faults() {
+        let mut cpu = machine(Quirks::default());
+        let result = try_exec(&mut cpu, Instruction::Return, &Keyboard::new());
+        assert_eq!(result, Err(EmulatorError::StackUnderflow { pc: 0x200 }));
+        assert_eq!(cpu.program_counter, 0x200);
+    }
+
+    #[test]
+    fn call_overflows_a_full_stack() {
+        let mut cpu = machine(Quirks::default());
+        for _ in 0..STACK_SIZE {
+            exec(&mut cpu, Instruction::Call(0x200));
+        }
+        let result = try_exec(&mut cpu, Instruction::Call(0x200), &Keyboard::new());
+        assert_eq!(result, Err(EmulatorError::StackOverflow { pc: 0x200 }));
+        assert_eq!(cpu.stack.len(), STACK_SIZE);
+    }
+
+    #[test]
+    fn jump() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::Jump(0x456));
+        assert_eq!(cpu.program_counter, 0x456);
+    }
+
+    #[test]
+    fn jump_offset_adds_v0() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[0] = 0x04;
+        cpu.registers[3] = 0x10;
+        exec(&mut cpu, Instruction::JumpOffset(0x345));
+        assert_eq!(cpu.program_counter, 0x349);
+    }
+
+    #[test]
+    fn jump_offset_adds_vx_with_quirk() {
+        let quirks = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        cpu.registers[0] = 0x04;
+        cpu.registers[3] = 0x10;
+        exec(&mut cpu, Instruction::JumpOffset(0x345));
+        assert_eq!(cpu.program_counter, 0x355);
+    }
+
+    #[test]
+    fn jump_offset_wraps_at_4k() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[0] = 0x10;
+        exec(&mut cpu, Instruction::JumpOffset(0xFF8));
+        assert_eq!(cpu.program_counter, 0x008);
+    }
+
+    #[test]
+    fn skips() {
+        let cases = [
+            (Instruction::RegEq(1, 5), true),
+            (Instruction::RegEq(1, 6), false),
+            (Instruction::RegNeq(1, 6), true),
+            (Instruction::RegNeq(1, 5), false),
+            (Instruction::RegEqReg(1, 2), true),
+            (Instruction::RegEqReg(1, 3), false),
+            (Instruction::RegNeqReg(1, 3), true),
+            (Instruction::RegNeqReg(1, 2), false),
+        ];
+        for &(instr, skips) in cases.iter() {
+            let mut cpu = machine(Quirks::default());
+            cpu.registers[1] = 5;
+            cpu.registers[2] = 5;
+            cpu.registers[3] = 6;
+            exec(&mut cpu, instr);
+            let expected = if skips { 0x204 } else { 0x202 };
+            assert_eq!(cpu.program_counter, expected, "{:?}", instr);
+        }
+    }
+
+    #[test]
+    fn skip_jumps_over_long_instruction() {
+        let mut cpu = machine(Quirks::default());
+        cpu.memory[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+        exec(&mut cpu, Instruction::RegEq(0, 0));
+        assert_eq!(cpu.program_counter, 0x206);
+    }
+
+    #[test]
+    fn key_skips() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[4] = 0xA;
+        exec_with_keys(&mut cpu, Instruction::KeyEq(4), &[0xA]);
+        assert_eq!(cpu.program_counter, 0x204);
+        exec_with_keys(&mut cpu, Instruction::KeyEq(4), &[0xB]);
+        assert_eq!(cpu.program_counter, 0x206);
+
+        exec_with_keys(&mut cpu, Instruction::KeyNeq(4), &[0xA]);
+        assert_eq!(cpu.program_counter, 0x208);
+        exec_with_keys(&mut cpu, Instruction::KeyNeq(4), &[]);
+        assert_eq!(cpu.program_counter, 0x20C);
+    }
 
-        // Decode instruction to enum
-        let decoded_instr = Instruction::decode(raw_instr);
+    #[test]
+    fn set_and_increment_register() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[15] = 0x55;
+        exec(&mut cpu, Instruction::SetReg(3, 0xF0));
+        exec(&mut cpu, Instruction::IncReg(3, 0x20));
+        // Wraps without touching VF
+        assert_eq!(cpu.registers[3], 0x10);
+        assert_eq!(cpu.registers[15], 0x55);
 
-        // Execute instruction
-        self.execute(decoded_instr, keyboard);
+        exec(&mut cpu, Instruction::RegSetReg(4, 3));
+        assert_eq!(cpu.registers[4], 0x10);
+    }
+
+    #[test]
+    fn logic_leaves_vf_alone() {
+        let quirks = Quirks::default();
+        let (x, y) = (0b1100, 0b1010);
+        assert_eq!(alu(quirks, Instruction::Or(1, 2), x, y), (0b1110, 0));
+        assert_eq!(alu(quirks, Instruction::And(1, 2), x, y), (0b1000, 0));
+        assert_eq!(alu(quirks, Instruction::XOr(1, 2), x, y), (0b0110, 0));
+
+        let mut cpu = machine(quirks);
+        cpu.registers[15] = 0x55;
+        exec(&mut cpu, Instruction::Or(1, 2));
+        assert_eq!(cpu.registers[15], 0x55);
+    }
+
+    #[test]
+    fn logic_resets_vf_with_quirk() {
+        let quirks = Quirks {
+            vf_reset: true,
+            ..Quirks::default()
+        };
+        for &instr in [
+            Instruction::Or(1, 2),
+            Instruction::And(1, 2),
+            Instruction::XOr(1, 2),
+            Instruction::Or(15, 2),
+        ]
+        .iter()
+        {
+            let mut cpu = machine(quirks);
+            cpu.registers[15] = 0x55;
+            cpu.registers[2] = 0xFF;
+            exec(&mut cpu, instr);
+            assert_eq!(cpu.registers[15], 0, "{:?}", instr);
+        }
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(quirks, Instruction::Add(1, 2), 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(quirks, Instruction::Add(1, 2), 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(quirks, Instruction::Add(1, 2), 0xF0, 0x20), (0x10, 1));
+        assert_eq!(alu(quirks, Instruction::Add(1, 2), 0x80, 0x00), (0x80, 0));
+    }
+
+    #[test]
+    fn add_into_vf_keeps_only_the_flag() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[15] = 0xF0;
+        cpu.registers[1] = 0x01;
+        exec(&mut cpu, Instruction::Add(15, 1));
+        assert_eq!(cpu.registers[15], 0);
 
-        self.update_timers();
+        cpu.registers[15] = 0xFF;
+        exec(&mut cpu, Instruction::Add(15, 1));
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn add_from_vf_uses_the_old_value() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[1] = 0xFF;
+        cpu.registers[15] = 0x02;
+        exec(&mut cpu, Instruction::Add(1, 15));
+        assert_eq!(cpu.registers[1], 0x01);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn sub_sets_vf_when_there_is_no_borrow() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(quirks, Instruction::Sub(1, 2), 0x07, 0x05), (0x02, 1));
+        assert_eq!(alu(quirks, Instruction::Sub(1, 2), 0x05, 0x05), (0x00, 1));
+        assert_eq!(alu(quirks, Instruction::Sub(1, 2), 0x05, 0x07), (0xFE, 0));
+        assert_eq!(alu(quirks, Instruction::Sub(1, 2), 0x00, 0xFF), (0x01, 0));
+    }
+
+    #[test]
+    fn rev_sub_sets_vf_when_there_is_no_borrow() {
+        let quirks = Quirks::default();
+        assert_eq!(
+            alu(quirks, Instruction::RevSub(1, 2), 0x05, 0x07),
+            (0x02, 1)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::RevSub(1, 2), 0x05, 0x05),
+            (0x00, 1)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::RevSub(1, 2), 0x07, 0x05),
+            (0xFE, 0)
+        );
+    }
+
+    #[test]
+    fn sub_into_vf_keeps_only_the_flag() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[1] = 0x01;
+        cpu.registers[15] = 0x03;
+        exec(&mut cpu, Instruction::Sub(15, 1));
+        assert_eq!(cpu.registers[15], 1);
+
+        cpu.registers[15] = 0x00;
+        exec(&mut cpu, Instruction::Sub(15, 1));
+        assert_eq!(cpu.registers[15], 0);
+
+        cpu.registers[15] = 0x05;
+        exec(&mut cpu, Instruction::RevSub(15, 1));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn shifts_vx_in_place() {
+        let quirks = Quirks::default();
+        assert_eq!(
+            alu(quirks, Instruction::ShiftR(1, 2), 0x81, 0x42),
+            (0x40, 1)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftR(1, 2), 0x80, 0x43),
+            (0x40, 0)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftL(1, 2), 0x81, 0x42),
+            (0x02, 1)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftL(1, 2), 0x41, 0xC2),
+            (0x82, 0)
+        );
+    }
+
+    #[test]
+    fn shifts_vy_with_quirk() {
+        let quirks = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        assert_eq!(
+            alu(quirks, Instruction::ShiftR(1, 2), 0x81, 0x42),
+            (0x21, 0)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftR(1, 2), 0x80, 0x43),
+            (0x21, 1)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftL(1, 2), 0x81, 0x42),
+            (0x84, 0)
+        );
+        assert_eq!(
+            alu(quirks, Instruction::ShiftL(1, 2), 0x01, 0xC2),
+            (0x84, 1)
+        );
+    }
+
+    #[test]
+    fn shift_into_vf_keeps_only_the_flag() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[15] = 0x81;
+        exec(&mut cpu, Instruction::ShiftR(15, 15));
+        assert_eq!(cpu.registers[15], 1);
+
+        cpu.registers[15] = 0x7F;
+        exec(&mut cpu, Instruction::ShiftL(15, 15));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn set_address() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::SetAddress(0x123));
+        assert_eq!(cpu.address_register, 0x123);
+
+        exec(&mut cpu, Instruction::LongSetAddress(0xBEEF));
+        assert_eq!(cpu.address_register, 0xBEEF);
+        assert_eq!(cpu.program_counter, 0x206);
+    }
+
+    #[test]
+    fn increment_address() {
+        let mut cpu = machine(Quirks::default());
+        cpu.address_register = 0xFFF0;
+        cpu.registers[2] = 0x20;
+        exec(&mut cpu, Instruction::IncAddress(2));
+        assert_eq!(cpu.address_register, 0x0010);
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn random_repeats_from_the_same_seed() {
+        let memory_random = Quirks {
+            memory_random: true,
+            ..Quirks::default()
+        };
+        for &quirks in [Quirks::default(), memory_random].iter() {
+            let run = |seed| {
+                let mut cpu = machine(quirks);
+                cpu.set_seed(seed);
+                (0..16)
+                    .map(|_| {
+                        exec(&mut cpu, Instruction::Random(1, 0xFF));
+                        cpu.registers[1]
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(run(1234), run(1234), "{:?}", quirks);
+            assert_ne!(run(1234), run(5678), "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn random_generator_is_saved() {
+        let mut cpu = machine(Quirks::default());
+        cpu.set_seed(99);
+        let state = cpu.save_state();
+        exec(&mut cpu, Instruction::Random(1, 0xFF));
+        let first = cpu.registers[1];
+
+        cpu.load_state(&state).unwrap();
+        exec(&mut cpu, Instruction::Random(1, 0xFF));
+        assert_eq!(cpu.registers[1], first);
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut cpu = machine(Quirks::default());
+        for _ in 0..100 {
+            exec(&mut cpu, Instruction::Random(1, 0x0F));
+            assert_eq!(cpu.registers[1] & 0xF0, 0);
+            exec(&mut cpu, Instruction::Random(2, 0x00));
+            assert_eq!(cpu.registers[2], 0);
+        }
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[1] = 10;
+        cpu.registers[2] = 5;
+        exec(&mut cpu, Instruction::SpriteAddress(0));
+
+        exec(&mut cpu, Instruction::Draw(1, 2, 5));
+        assert_eq!(cpu.registers[15], 0);
+        assert!(cpu.display.get_pixel(10, 5));
+        assert!(!cpu.display.get_pixel(11, 6));
+        assert!(cpu.display.get_pixel(13, 9));
+        assert_eq!(lit_pixels(&cpu).len(), 14);
+
+        exec(&mut cpu, Instruction::Draw(1, 2, 5));
+        assert_eq!(cpu.registers[15], 1);
+        assert!(lit_pixels(&cpu).is_empty());
+    }
+
+    #[test]
+    fn draw_into_vf_coordinates() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[15] = 3;
+        exec(&mut cpu, Instruction::SpriteAddress(0));
+        exec(&mut cpu, Instruction::Draw(15, 15, 1));
+        assert!(cpu.display.get_pixel(3, 3));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn draw_wraps_at_the_edges() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[1] = 62;
+        cpu.registers[2] = 30;
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xFF; 4]);
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::Draw(1, 2, 4));
+
+        assert_eq!(lit_pixels(&cpu).len(), 32);
+        assert!(cpu.display.get_pixel(63, 31));
+        assert!(cpu.display.get_pixel(0, 31));
+        assert!(cpu.display.get_pixel(5, 1));
+        assert!(!cpu.display.get_pixel(6, 1));
+        assert!(!cpu.display.get_pixel(5, 2));
+    }
+
+    #[test]
+    fn draw_clips_at_the_edges_with_quirk() {
+        let quirks = Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        cpu.registers[1] = 62;
+        cpu.registers[2] = 30;
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xFF; 4]);
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::Draw(1, 2, 4));
+
+        let mut expected = vec![(62, 30), (63, 30), (62, 31), (63, 31)];
+        expected.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(lit_pixels(&cpu), expected);
+    }
+
+    #[test]
+    fn draw_wraps_the_starting_position_even_when_clipping() {
+        let quirks = Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        cpu.registers[1] = 64 + 2;
+        cpu.registers[2] = 32 + 1;
+        cpu.memory[0x300] = 0x80;
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::Draw(1, 2, 1));
+        assert_eq!(lit_pixels(&cpu), vec![(2, 1)]);
+    }
+
+    #[test]
+    fn draw_waits_for_vblank_with_quirk() {
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        exec(&mut cpu, Instruction::SpriteAddress(0));
+        let pc = cpu.program_counter;
+
+        exec(&mut cpu, Instruction::Draw(0, 0, 5));
+        assert_eq!(cpu.program_counter, pc);
+        assert!(lit_pixels(&cpu).is_empty());
+
+        cpu.vblank = true;
+        exec(&mut cpu, Instruction::Draw(0, 0, 5));
+        assert_eq!(cpu.program_counter, pc + 2);
+        assert!(!lit_pixels(&cpu).is_empty());
+        assert!(!cpu.vblank);
+    }
+
+    #[test]
+    fn draw_big_sprite_in_high_res() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::HighRes);
+        cpu.registers[1] = 120;
+        cpu.registers[2] = 60;
+        cpu.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::Draw(1, 2, 0));
+
+        assert_eq!(lit_pixels(&cpu).len(), 256);
+        assert!(cpu.display.get_pixel(127, 63));
+        assert!(cpu.display.get_pixel(7, 11));
+        assert!(!cpu.display.get_pixel(8, 12));
+    }
+
+    #[test]
+    fn draw_to_both_planes_reads_two_sprites() {
+        let mut cpu = machine(Quirks::default());
+        cpu.memory[0x300] = 0xC0;
+        cpu.memory[0x301] = 0x80;
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::SelectPlanes(3));
+        exec(&mut cpu, Instruction::Draw(0, 0, 1));
+
+        assert_eq!(cpu.display.get_color(0, 0), 3);
+        assert_eq!(cpu.display.get_color(1, 0), 1);
+
+        exec(&mut cpu, Instruction::SelectPlanes(2));
+        exec(&mut cpu, Instruction::Draw(0, 0, 1));
+        assert_eq!(cpu.display.get_color(0, 0), 1);
+        assert_eq!(cpu.display.get_color(1, 0), 3);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn resolution_switches_clear_the_screen() {
+        let mut cpu = machine(Quirks::default());
+        cpu.display.flip(1, true, 0, 0);
+        exec(&mut cpu, Instruction::HighRes);
+        assert_eq!(cpu.display.width(), 128);
+        assert!(lit_pixels(&cpu).is_empty());
+
+        exec(&mut cpu, Instruction::LowRes);
+        assert_eq!(cpu.display.width(), 64);
+    }
+
+    #[test]
+    fn scrolls() {
+        let cases = [
+            (Instruction::ScrollDown(3), (10, 13)),
+            (Instruction::ScrollUp(3), (10, 7)),
+            (Instruction::ScrollRight, (14, 10)),
+            (Instruction::ScrollLeft, (6, 10)),
+        ];
+        for &(instr, lit) in cases.iter() {
+            let mut cpu = machine(Quirks::default());
+            cpu.display.flip(1, true, 10, 10);
+            exec(&mut cpu, instr);
+            assert_eq!(lit_pixels(&cpu), vec![lit], "{:?}", instr);
+        }
+    }
+
+    #[test]
+    fn scroll_only_moves_selected_planes() {
+        let mut cpu = machine(Quirks::default());
+        cpu.display.flip(1, true, 10, 10);
+        cpu.display.flip(2, true, 10, 10);
+        exec(&mut cpu, Instruction::SelectPlanes(2));
+        exec(&mut cpu, Instruction::ScrollRight);
+        assert_eq!(cpu.display.get_color(10, 10), 1);
+        assert_eq!(cpu.display.get_color(14, 10), 2);
+    }
+
+    #[test]
+    fn timers() {
+        let audio = RecordingAudio::new();
+        let mut cpu = CPU::new(Box::new(audio.clone()), Quirks::default());
+        cpu.registers[1] = 200;
+
+        exec(&mut cpu, Instruction::SetDelay(1));
+        assert_eq!(cpu.delay_timer, 200);
+        cpu.delay_timer = 42;
+        exec(&mut cpu, Instruction::GetDelay(2));
+        assert_eq!(cpu.registers[2], 42);
+
+        exec(&mut cpu, Instruction::SetSound(1));
+        assert_eq!(cpu.sound_timer, 200);
+        assert!(audio.is_playing());
+
+        exec(&mut cpu, Instruction::SetSound(3));
+        assert_eq!(cpu.sound_timer, 0);
+        assert!(!audio.is_playing());
+    }
+
+    #[test]
+    fn timers_count_down_once_per_frame() {
+        let audio = RecordingAudio::new();
+        let mut cpu = CPU::new(Box::new(audio.clone()), Quirks::default());
+        cpu.registers[1] = 2;
+        exec(&mut cpu, Instruction::SetDelay(1));
+        exec(&mut cpu, Instruction::SetSound(1));
+        exec(&mut cpu, Instruction::SetReg(2, 0));
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (2, 2));
+
+        cpu.tick_frame();
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (1, 1));
+        assert!(audio.is_playing());
+        assert!(cpu.vblank);
+
+        cpu.tick_frame();
+        cpu.tick_frame();
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (0, 0));
+        assert_eq!(audio.events(), vec![ToneEvent::Start, ToneEvent::Stop]);
+    }
+
+    #[test]
+    fn wait_key_completes_when_the_key_is_released() {
+        let mut cpu = machine(Quirks::default());
+        let mut keyboard = Keyboard::new();
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(cpu.program_counter, 0x200);
+
+        keyboard.set_key(0xC, true);
+        for _ in 0..3 {
+            try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+            assert_eq!(cpu.program_counter, 0x200);
+        }
+
+        keyboard.set_key(0xC, false);
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(cpu.program_counter, 0x202);
+        assert_eq!(cpu.registers[5], 0xC);
+    }
+
+    #[test]
+    fn wait_key_ignores_a_key_held_from_before() {
+        let mut cpu = machine(Quirks::default());
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0x1, true);
+        keyboard.end_frame();
+
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        keyboard.set_key(0x1, false);
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(cpu.program_counter, 0x200);
+
+        keyboard.set_key(0x2, true);
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        keyboard.set_key(0x2, false);
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(cpu.registers[5], 0x2);
+    }
+
+    #[test]
+    fn wait_key_takes_a_tap_made_earlier_in_the_frame() {
+        let mut cpu = machine(Quirks::default());
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0x7, true);
+        keyboard.set_key(0x7, false);
+
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(cpu.program_counter, 0x202);
+        assert_eq!(cpu.registers[5], 0x7);
+
+        // The same tap doesn't answer a second prompt
+        try_exec(&mut cpu, Instruction::WaitKey(6), &keyboard).unwrap();
+        assert_eq!(cpu.program_counter, 0x202);
+
+        cpu.tick_frame();
+        keyboard.end_frame();
+        keyboard.set_key(0x7, true);
+        keyboard.set_key(0x7, false);
+        try_exec(&mut cpu, Instruction::WaitKey(6), &keyboard).unwrap();
+        assert_eq!(cpu.registers[6], 0x7);
+    }
+
+    #[test]
+    fn wait_key_takes_a_held_key_with_quirk() {
+        let quirks = Quirks {
+            wait_key_on_press: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        exec(&mut cpu, Instruction::WaitKey(5));
+        assert_eq!(cpu.program_counter, 0x200);
+
+        exec_with_keys(&mut cpu, Instruction::WaitKey(5), &[0xC]);
+        assert_eq!(cpu.program_counter, 0x202);
+        assert_eq!(cpu.registers[5], 0xC);
+    }
+
+    #[test]
+    fn font_addresses() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[1] = 0xA;
+        exec(&mut cpu, Instruction::SpriteAddress(1));
+        let address = cpu.address_register as usize;
+        assert_eq!(cpu.memory[address..address + 5], FONT[50..55]);
+
+        cpu.registers[1] = 3;
+        exec(&mut cpu, Instruction::BigSpriteAddress(1));
+        let address = cpu.address_register as usize;
+        assert_eq!(cpu.memory[address..address + 10], BIG_FONT[30..40]);
+    }
+
+    #[test]
+    fn bcd() {
+        for &(value, digits) in [
+            (255, [2, 5, 5]),
+            (0, [0, 0, 0]),
+            (7, [0, 0, 7]),
+            (40, [0, 4, 0]),
+        ]
+        .iter()
+        {
+            let mut cpu = machine(Quirks::default());
+            cpu.address_register = 0x300;
+            cpu.registers[15] = value;
+            exec(&mut cpu, Instruction::BCD(15));
+            assert_eq!(cpu.memory[0x300..0x303], digits, "{}", value);
+            assert_eq!(cpu.address_register, 0x300);
+        }
+    }
+
+    #[test]
+    fn dump_and_load_registers() {
+        let mut cpu = machine(Quirks::default());
+        cpu.address_register = 0x300;
+        cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        exec(&mut cpu, Instruction::RegDump(2));
+        assert_eq!(cpu.memory[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(cpu.address_register, 0x300);
+
+        cpu.memory[0x300..0x304].copy_from_slice(&[9, 8, 7, 6]);
+        exec(&mut cpu, Instruction::RegLoad(2));
+        assert_eq!(cpu.registers[..4], [9, 8, 7, 4]);
+        assert_eq!(cpu.address_register, 0x300);
+    }
+
+    #[test]
+    fn dump_and_load_increment_i_with_quirk() {
+        let quirks = Quirks {
+            load_store_increments_i: true,
+            ..Quirks::default()
+        };
+        let mut cpu = machine(quirks);
+        cpu.address_register = 0x300;
+        cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        exec(&mut cpu, Instruction::RegDump(2));
+        assert_eq!(cpu.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.address_register, 0x303);
+
+        exec(&mut cpu, Instruction::RegLoad(0));
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(cpu.address_register, 0x304);
+    }
+
+    #[test]
+    fn dump_and_load_increment_i_by_x_on_chip48() {
+        let mut cpu = machine(Quirks::chip48());
+        cpu.address_register = 0x300;
+        cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        exec(&mut cpu, Instruction::RegDump(2));
+        assert_eq!(cpu.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.address_register, 0x302);
+
+        exec(&mut cpu, Instruction::RegLoad(0));
+        assert_eq!(cpu.registers[0], 3);
+        assert_eq!(cpu.address_register, 0x302);
+
+        // SUPER-CHIP leaves I alone
+        let mut cpu = machine(Quirks::superchip());
+        cpu.address_register = 0x300;
+        exec(&mut cpu, Instruction::RegDump(2));
+        assert_eq!(cpu.address_register, 0x300);
+    }
+
+    #[test]
+    fn load_registers_including_vf() {
+        let mut cpu = machine(Quirks::default());
+        cpu.address_register = 0x300;
+        cpu.memory[0x30F] = 0xAB;
+        exec(&mut cpu, Instruction::RegLoad(15));
+        assert_eq!(cpu.registers[15], 0xAB);
+    }
+
+    #[test]
+    fn register_dump_past_the_end_of_memory_faults() {
+        let mut cpu = machine(Quirks::default());
+        cpu.address_register = 0xFFF;
+        let result = try_exec(&mut cpu, Instruction::RegDump(1), &Keyboard::new());
+        assert_eq!(
+            result,
+            Err(EmulatorError::MemoryOutOfBounds {
+                addr: 0x1000,
+                pc: 0x200
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x200);
+
+        // XO-CHIP has memory up to 0xFFFF
+        let mut cpu = machine(Quirks::xochip());
+        cpu.address_register = 0xFFF;
+        exec(&mut cpu, Instruction::RegDump(1));
+        cpu.address_register = 0xFFFF;
+        let result = try_exec(&mut cpu, Instruction::RegDump(1), &Keyboard::new());
+        assert_eq!(
+            result,
+            Err(EmulatorError::MemoryOutOfBounds {
+                addr: 0x10000,
+                pc: 0x202
+            })
+        );
+    }
+
+    #[test]
+    fn roms_have_to_fit_in_memory() {
+        let mut cpu = machine(Quirks::default());
+        assert_eq!(
+            cpu.load_rom(&[0; 3585]),
+            Err(EmulatorError::RomTooLarge {
+                size: 3585,
+                max: 3584
+            })
+        );
+        assert_eq!(cpu.load_rom(&[0; 3584]), Ok(()));
+
+        let mut cpu = machine(Quirks::xochip());
+        assert_eq!(cpu.load_rom(&[0; 65024]), Ok(()));
+        assert_eq!(
+            cpu.load_rom(&[0; 65025]),
+            Err(EmulatorError::RomTooLarge {
+                size: 65025,
+                max: 65024
+            })
+        );
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        let mut cpu = machine(Quirks::default());
+        cpu.address_register = 0x300;
+        cpu.registers[2..5].copy_from_slice(&[1, 2, 3]);
+        exec(&mut cpu, Instruction::SaveRange(2, 4));
+        assert_eq!(cpu.memory[0x300..0x303], [1, 2, 3]);
+
+        // Counting down stores the registers in reverse
+        exec(&mut cpu, Instruction::SaveRange(4, 2));
+        assert_eq!(cpu.memory[0x300..0x303], [3, 2, 1]);
+
+        exec(&mut cpu, Instruction::LoadRange(7, 9));
+        assert_eq!(cpu.registers[7..10], [3, 2, 1]);
+        exec(&mut cpu, Instruction::LoadRange(9, 7));
+        assert_eq!(cpu.registers[7..10], [1, 2, 3]);
+        assert_eq!(cpu.address_register, 0x300);
+    }
+
+    #[test]
+    fn save_and_load_flags() {
+        let mut cpu = machine(Quirks::default());
+        cpu.registers[..3].copy_from_slice(&[1, 2, 3]);
+        exec(&mut cpu, Instruction::SaveFlags(1));
+        cpu.registers = [0; 16];
+        exec(&mut cpu, Instruction::LoadFlags(2));
+        assert_eq!(cpu.registers[..3], [1, 2, 0]);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let audio = RecordingAudio::new();
+        let mut cpu = CPU::new(Box::new(audio.clone()), Quirks::default());
+        let pattern = [0xF0; 16];
+        cpu.memory[0x300..0x310].copy_from_slice(&pattern);
+        cpu.registers[1] = 100;
+
+        exec(&mut cpu, Instruction::SetPitch(1));
+        assert!(audio.events().is_empty());
+
+        exec(&mut cpu, Instruction::SetAddress(0x300));
+        exec(&mut cpu, Instruction::LoadAudioPattern);
+        cpu.registers[1] = 80;
+        exec(&mut cpu, Instruction::SetPitch(1));
+        assert_eq!(
+            audio.events(),
+            vec![
+                ToneEvent::Pattern(pattern, 100),
+                ToneEvent::Pattern(pattern, 80)
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_halts_in_place() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::Exit);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.program_counter, 0x200);
+
+        exec(&mut cpu, Instruction::SetReg(0, 1));
+        assert_eq!(cpu.registers[0], 0);
+    }
+
+    #[test]
+    fn sys_call_is_ignored() {
+        let mut cpu = machine(Quirks::default());
+        exec(&mut cpu, Instruction::SysCall(0x123));
+        assert_eq!(cpu.program_counter, 0x202);
+    }
+
+    #[test]
+    fn unknown_opcode_faults() {
+        let mut cpu = machine(Quirks::default());
+        cpu.memory[0x200..0x202].copy_from_slice(&[0x50, 0x01]);
+        let result = cpu.step(&Keyboard::new());
+        assert_eq!(
+            result,
+            Err(EmulatorError::UnknownOpcode {
+                opcode: 0x5001,
+                pc: 0x200
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x200);
+    }
+
+    /// A machine part way through a program, with something in every part of its state.
+    fn busy_machine() -> CPU {
+        let mut cpu = machine(Quirks::default());
+        cpu.load_rom(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        cpu.set_seed(7);
+        cpu.registers[3] = 0x33;
+        exec(&mut cpu, Instruction::Call(0x300));
+        exec(&mut cpu, Instruction::SetDelay(3));
+        exec(&mut cpu, Instruction::SaveFlags(3));
+        exec(&mut cpu, Instruction::HighRes);
+        exec(&mut cpu, Instruction::SpriteAddress(3));
+        exec(&mut cpu, Instruction::Draw(3, 3, 5));
+        exec(&mut cpu, Instruction::Random(4, 0xFF));
+        cpu
+    }
+
+    #[test]
+    fn save_states_round_trip() {
+        let cpu = busy_machine();
+        let state = cpu.save_state();
+
+        let mut restored = machine(Quirks::default());
+        restored.load_rom(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.stack, vec![0x202]);
+        assert_eq!(restored.delay_timer, 0x33);
+        assert_eq!(restored.rpl_flags[3], 0x33);
+        assert!(restored.display.is_hires());
+        assert_eq!(lit_pixels(&restored), lit_pixels(&cpu));
+    }
+
+    #[test]
+    fn save_states_are_checked_before_loading() {
+        let mut cpu = busy_machine();
+        let state = cpu.save_state();
+        let other = machine(Quirks::default()).save_state();
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            cpu.load_state(&bad_magic),
+            Err(EmulatorError::InvalidSaveState("not a save state"))
+        );
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(
+            cpu.load_state(&future),
+            Err(EmulatorError::UnsupportedSaveStateVersion(99))
+        );
+
+        for &len in [2, 40, state.len() - 1].iter() {
+            assert_eq!(
+                cpu.load_state(&state[..len]),
+                Err(EmulatorError::InvalidSaveState("data ends early")),
+                "{} bytes",
+                len
+            );
+        }
+
+        assert_eq!(
+            cpu.load_state(&other),
+            Err(EmulatorError::SaveStateRomMismatch)
+        );
+
+        // None of that touched the machine
+        assert_eq!(cpu.save_state(), state);
+    }
+
+    /// Rewrites a current save state of a machine with the standard memory in the layout
+    /// an older version used.
+    fn older_state(state: &[u8], version: u16) -> Vec<u8> {
+        // The resolution and pixels come after the waits, then the memory's size and data
+        let pixels_end = state.len() - (4 + MEMORY_SIZE);
+        let end = pixels_end - (1 + Framebuffer::new().pixels().len());
+        let removed = match version {
+            SAVE_STATE_VERSION_WITHOUT_RANDOM => 10 + 5,
+            SAVE_STATE_VERSION_WITHOUT_WAITS => 5,
+            _ => 0,
+        };
+        let mut old = state[..end - removed].to_vec();
+        old.extend_from_slice(&state[end..pixels_end]);
+        // Memory was always 64 KiB
+        old.extend_from_slice(&state[pixels_end + 4..]);
+        old.resize(old.len() + EXTENDED_MEMORY_SIZE - MEMORY_SIZE, 0);
+        old[4..6].copy_from_slice(&version.to_le_bytes());
+        old
+    }
+
+    #[test]
+    fn save_states_keep_waits_in_progress() {
+        let mut cpu = machine(Quirks::default());
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0xC, true);
+        try_exec(&mut cpu, Instruction::WaitKey(5), &keyboard).unwrap();
+        cpu.tick_frame();
+        let state = cpu.save_state();
+
+        let mut restored = machine(Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert!(restored.vblank);
+        keyboard.end_frame();
+        keyboard.set_key(0xC, false);
+        try_exec(&mut restored, Instruction::WaitKey(5), &keyboard).unwrap();
+        assert_eq!(restored.program_counter, 0x202);
+        assert_eq!(restored.registers[5], 0xC);
+    }
+
+    #[test]
+    fn older_save_states_still_load() {
+        let mut cpu = busy_machine();
+        cpu.tick_frame();
+        let state = cpu.save_state();
+
+        for &version in [1, 2, 3].iter() {
+            let mut restored = busy_machine();
+            exec(&mut restored, Instruction::Return);
+            restored.load_state(&older_state(&state, version)).unwrap();
+            assert_eq!(restored.program_counter, cpu.program_counter);
+            assert_eq!(restored.stack, cpu.stack);
+            assert_eq!(restored.memory, cpu.memory);
+            if version < SAVE_STATE_VERSION_WITHOUT_MEMORY_SIZE {
+                assert!(!restored.vblank);
+            }
+        }
 
-        self.draw_display(display);
+        // Anything stored past the end of the memory can't be dropped
+        let mut old = older_state(&state, 3);
+        *old.last_mut().unwrap() = 1;
+        assert_eq!(
+            busy_machine().load_state(&old),
+            Err(EmulatorError::InvalidSaveState(
+                "memory is a different size"
+            ))
+        );
     }
 
-    pub fn load_rom(&mut self, data: &[u8]) {
-        self.memory[0x200..(0x200 + data.len())].copy_from_slice(data);
+    #[test]
+    fn save_states_only_load_into_the_same_size_of_memory() {
+        let xochip = machine(Quirks::xochip()).save_state();
+        assert_eq!(
+            machine(Quirks::default()).load_state(&xochip),
+            Err(EmulatorError::InvalidSaveState(
+                "memory is a different size"
+            ))
+        );
+        assert_eq!(machine(Quirks::xochip()).load_state(&xochip), Ok(()));
     }
 }
@@ -0,0 +1,206 @@
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+
+/// Colours for each combination of the two bitplanes, as RGB.
+///
+/// Index 0 is the background, 1 and 2 are pixels lit in only the first or second plane, and
+/// 3 is both planes lit. Programs that only use the first plane just see colours 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        self.0[index as usize & 0x3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette([
+            [0x00, 0x00, 0x00],
+            [0xFF, 0xFF, 0xFF],
+            [0xAA, 0xAA, 0xAA],
+            [0x55, 0x55, 0x55],
+        ])
+    }
+}
+
+/// The machine's screen, either the standard 64x32 or the SUPER-CHIP 128x64 high resolution.
+///
+/// Each pixel holds one bit per XO-CHIP bitplane, so it reads as a colour index from 0 to 3.
+/// Operations that draw or scroll take a mask of the planes they apply to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            pixels: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_DISPLAY_WIDTH
+    }
+
+    /// Whether the pixel is lit in any plane.
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.get_color(x, y) != 0
+    }
+
+    pub fn get_color(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub(crate) fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Overwrites every pixel, e.g. when restoring a save state.
+    pub(crate) fn restore(&mut self, pixels: &[u8]) {
+        self.pixels.copy_from_slice(pixels);
+    }
+
+    /// Switches resolution, which also clears the screen.
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        };
+
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+    }
+
+    pub(crate) fn clear(&mut self, planes: u8) {
+        for p in self.pixels.iter_mut() {
+            *p &= !planes;
+        }
+    }
+
+    /// XORs a sprite bit onto one plane, returning whether a lit pixel was turned off.
+    pub(crate) fn flip(&mut self, plane: u8, sprite_bit: bool, x: usize, y: usize) -> bool {
+        let index = y * self.width + x;
+        let pixel = self.pixels[index] & plane != 0;
+
+        if sprite_bit {
+            self.pixels[index] ^= plane;
+        }
+
+        pixel && sprite_bit
+    }
+
+    pub(crate) fn scroll_down(&mut self, planes: u8, rows: usize) {
+        self.scroll(planes, 0, rows as isize);
+    }
+
+    pub(crate) fn scroll_up(&mut self, planes: u8, rows: usize) {
+        self.scroll(planes, 0, -(rows as isize));
+    }
+
+    pub(crate) fn scroll_right(&mut self, planes: u8, columns: usize) {
+        self.scroll(planes, columns as isize, 0);
+    }
+
+    pub(crate) fn scroll_left(&mut self, planes: u8, columns: usize) {
+        self.scroll(planes, -(columns as isize), 0);
+    }
+
+    fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let source = self.pixels.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (src_x, src_y) = (x as isize - dx, y as isize - dy);
+                let in_bounds = src_x >= 0
+                    && src_y >= 0
+                    && (src_x as usize) < self.width
+                    && (src_y as usize) < self.height;
+                let shifted = if in_bounds {
+                    source[src_y as usize * self.width + src_x as usize]
+                } else {
+                    0
+                };
+
+                let index = y * self.width + x;
+                self.pixels[index] = (self.pixels[index] & !planes) | (shifted & planes);
+            }
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+/// Somewhere to present the framebuffer, once at the end of every 60Hz frame the
+/// `Scheduler` runs.
+pub trait DisplayTarget {
+    fn draw(&mut self, display: &Framebuffer);
+}
+
+/// A display target that ignores every frame.
+pub struct NullDisplay;
+
+impl DisplayTarget for NullDisplay {
+    fn draw(&mut self, _display: &Framebuffer) {}
+}
+
+/// A display target that keeps a copy of the most recent frame.
+pub struct RecordingDisplay {
+    frame: Framebuffer,
+    frames_drawn: usize,
+}
+
+impl RecordingDisplay {
+    pub fn new() -> Self {
+        RecordingDisplay {
+            frame: Framebuffer::new(),
+            frames_drawn: 0,
+        }
+    }
+
+    pub fn frame(&self) -> &Framebuffer {
+        &self.frame
+    }
+
+    pub fn frames_drawn(&self) -> usize {
+        self.frames_drawn
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.frame.get_pixel(x, y)
+    }
+}
+
+impl Default for RecordingDisplay {
+    fn default() -> Self {
+        RecordingDisplay::new()
+    }
+}
+
+impl DisplayTarget for RecordingDisplay {
+    fn draw(&mut self, display: &Framebuffer) {
+        self.frame.clone_from(display);
+        self.frames_drawn += 1;
+    }
+}
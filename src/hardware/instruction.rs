@@ -1,12 +1,25 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
+    SysCall(u16),
     Clear,
     Return,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
     Jump(u16),
     Call(u16),
     RegEq(usize, u8),
     RegNeq(usize, u8),
     RegEqReg(usize, usize),
+    SaveRange(usize, usize),
+    LoadRange(usize, usize),
     SetReg(usize, u8),
     IncReg(usize, u8),
     RegSetReg(usize, usize),
@@ -15,11 +28,12 @@ pub enum Instruction {
     XOr(usize, usize),
     Add(usize, usize),
     Sub(usize, usize),
-    ShiftR(usize),
+    ShiftR(usize, usize),
     RevSub(usize, usize),
-    ShiftL(usize),
+    ShiftL(usize, usize),
     RegNeqReg(usize, usize),
     SetAddress(u16),
+    LongSetAddress(u16),
     JumpOffset(u16),
     Random(usize, u8),
     Draw(usize, usize, u8),
@@ -34,11 +48,16 @@ pub enum Instruction {
     RegDump(usize),
     RegLoad(usize),
     BCD(usize),
-    NoOp,
+    BigSpriteAddress(usize),
+    SaveFlags(usize),
+    LoadFlags(usize),
+    SelectPlanes(u8),
+    LoadAudioPattern,
+    SetPitch(usize),
 }
 
 impl Instruction {
-    pub fn decode(opcode: u16) -> Instruction {
+    pub fn decode(opcode: u16) -> Option<Instruction> {
         let nibbles = (
             (opcode & 0xF000) >> 12,
             (opcode & 0x0F00) >> 8,
@@ -52,14 +71,24 @@ impl Instruction {
         let value = (opcode & 0x00FF) as u8;
         let short_value = (opcode & 0x000F) as u8;
 
-        match nibbles {
+        let instr = match nibbles {
             (0x0, 0x0, 0xE, 0x0) => Instruction::Clear,
             (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+            (0x0, 0x0, 0xC, _) => Instruction::ScrollDown(short_value),
+            (0x0, 0x0, 0xD, _) => Instruction::ScrollUp(short_value),
+            (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::LowRes,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::HighRes,
+            (0x0, _, _, _) => Instruction::SysCall(address),
             (0x1, _, _, _) => Instruction::Jump(address),
             (0x2, _, _, _) => Instruction::Call(address),
             (0x3, _, _, _) => Instruction::RegEq(x_register, value),
             (0x4, _, _, _) => Instruction::RegNeq(x_register, value),
-            (0x5, _, _, 0) => Instruction::RegEqReg(x_register, y_register),
+            (0x5, _, _, 0x0) => Instruction::RegEqReg(x_register, y_register),
+            (0x5, _, _, 0x2) => Instruction::SaveRange(x_register, y_register),
+            (0x5, _, _, 0x3) => Instruction::LoadRange(x_register, y_register),
             (0x6, _, _, _) => Instruction::SetReg(x_register, value),
             (0x7, _, _, _) => Instruction::IncReg(x_register, value),
             (0x8, _, _, 0x0) => Instruction::RegSetReg(x_register, y_register),
@@ -68,9 +97,9 @@ impl Instruction {
             (0x8, _, _, 0x3) => Instruction::XOr(x_register, y_register),
             (0x8, _, _, 0x4) => Instruction::Add(x_register, y_register),
             (0x8, _, _, 0x5) => Instruction::Sub(x_register, y_register),
-            (0x8, _, _, 0x6) => Instruction::ShiftR(x_register),
+            (0x8, _, _, 0x6) => Instruction::ShiftR(x_register, y_register),
             (0x8, _, _, 0x7) => Instruction::RevSub(x_register, y_register),
-            (0x8, _, _, 0xE) => Instruction::ShiftL(x_register),
+            (0x8, _, _, 0xE) => Instruction::ShiftL(x_register, y_register),
             (0x9, _, _, 0x0) => Instruction::RegNeqReg(x_register, y_register),
             (0xA, _, _, _) => Instruction::SetAddress(address),
             (0xB, _, _, _) => Instruction::JumpOffset(address),
@@ -78,19 +107,174 @@ impl Instruction {
             (0xD, _, _, _) => Instruction::Draw(x_register, y_register, short_value),
             (0xE, _, 0x9, 0xE) => Instruction::KeyEq(x_register),
             (0xE, _, 0xA, 0x1) => Instruction::KeyNeq(x_register),
+            (0xF, _, 0x0, 0x1) => Instruction::SelectPlanes(x_register as u8),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::LoadAudioPattern,
             (0xF, _, 0x0, 0x7) => Instruction::GetDelay(x_register),
             (0xF, _, 0x0, 0xA) => Instruction::WaitKey(x_register),
             (0xF, _, 0x1, 0x5) => Instruction::SetDelay(x_register),
             (0xF, _, 0x1, 0x8) => Instruction::SetSound(x_register),
             (0xF, _, 0x1, 0xE) => Instruction::IncAddress(x_register),
             (0xF, _, 0x2, 0x9) => Instruction::SpriteAddress(x_register),
+            (0xF, _, 0x3, 0x0) => Instruction::BigSpriteAddress(x_register),
+            (0xF, _, 0x3, 0xA) => Instruction::SetPitch(x_register),
             (0xF, _, 0x3, 0x3) => Instruction::BCD(x_register),
             (0xF, _, 0x5, 0x5) => Instruction::RegDump(x_register),
             (0xF, _, 0x6, 0x5) => Instruction::RegLoad(x_register),
-            _ => {
-                println!("Unknown Instruction: {:X}", opcode);
-                Instruction::NoOp
+            (0xF, _, 0x7, 0x5) => Instruction::SaveFlags(x_register),
+            (0xF, _, 0x8, 0x5) => Instruction::LoadFlags(x_register),
+            _ => return None,
+        };
+
+        Some(instr)
+    }
+
+    /// Whether the opcode is the first half of a four byte instruction, and has to be decoded
+    /// with `decode_long` together with the word that follows it.
+    pub fn is_long(opcode: u16) -> bool {
+        opcode == 0xF000
+    }
+
+    pub fn decode_long(opcode: u16, operand: u16) -> Option<Instruction> {
+        match opcode {
+            0xF000 => Some(Instruction::LongSetAddress(operand)),
+            _ => None,
+        }
+    }
+
+    /// The bytes of the instruction as they appear in memory, the reverse of `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        let xy =
+            |base: u16, x: usize, y: usize, n: u16| base | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |base: u16, x: usize, value: u8| base | (x as u16) << 8 | value as u16;
+
+        let opcode = match *self {
+            Instruction::SysCall(address) => address & 0x0FFF,
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown(rows) => 0x00C0 | rows as u16,
+            Instruction::ScrollUp(rows) => 0x00D0 | rows as u16,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(address) => 0x1000 | address,
+            Instruction::Call(address) => 0x2000 | address,
+            Instruction::RegEq(x, value) => xnn(0x3000, x, value),
+            Instruction::RegNeq(x, value) => xnn(0x4000, x, value),
+            Instruction::RegEqReg(x, y) => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange(x, y) => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xy(0x5000, x, y, 0x3),
+            Instruction::SetReg(x, value) => xnn(0x6000, x, value),
+            Instruction::IncReg(x, value) => xnn(0x7000, x, value),
+            Instruction::RegSetReg(x, y) => xy(0x8000, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8000, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8000, x, y, 0x2),
+            Instruction::XOr(x, y) => xy(0x8000, x, y, 0x3),
+            Instruction::Add(x, y) => xy(0x8000, x, y, 0x4),
+            Instruction::Sub(x, y) => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftR(x, y) => xy(0x8000, x, y, 0x6),
+            Instruction::RevSub(x, y) => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftL(x, y) => xy(0x8000, x, y, 0xE),
+            Instruction::RegNeqReg(x, y) => xy(0x9000, x, y, 0x0),
+            Instruction::SetAddress(address) => 0xA000 | address,
+            Instruction::LongSetAddress(address) => {
+                let [high, low] = address.to_be_bytes();
+                return vec![0xF0, 0x00, high, low];
             }
+            Instruction::JumpOffset(address) => 0xB000 | address,
+            Instruction::Random(x, value) => xnn(0xC000, x, value),
+            Instruction::Draw(x, y, height) => xy(0xD000, x, y, height as u16),
+            Instruction::KeyEq(x) => xnn(0xE000, x, 0x9E),
+            Instruction::KeyNeq(x) => xnn(0xE000, x, 0xA1),
+            Instruction::SelectPlanes(planes) => xnn(0xF000, planes as usize, 0x01),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::GetDelay(x) => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey(x) => xnn(0xF000, x, 0x0A),
+            Instruction::SetDelay(x) => xnn(0xF000, x, 0x15),
+            Instruction::SetSound(x) => xnn(0xF000, x, 0x18),
+            Instruction::IncAddress(x) => xnn(0xF000, x, 0x1E),
+            Instruction::SpriteAddress(x) => xnn(0xF000, x, 0x29),
+            Instruction::BigSpriteAddress(x) => xnn(0xF000, x, 0x30),
+            Instruction::SetPitch(x) => xnn(0xF000, x, 0x3A),
+            Instruction::BCD(x) => xnn(0xF000, x, 0x33),
+            Instruction::RegDump(x) => xnn(0xF000, x, 0x55),
+            Instruction::RegLoad(x) => xnn(0xF000, x, 0x65),
+            Instruction::SaveFlags(x) => xnn(0xF000, x, 0x75),
+            Instruction::LoadFlags(x) => xnn(0xF000, x, 0x85),
+        };
+
+        opcode.to_be_bytes().to_vec()
+    }
+
+    /// Writes the instruction in the classic syntax, with `address` and `long_address`
+    /// formatting the addresses it uses, e.g. to put labels in their place.
+    pub(crate) fn format_classic(
+        &self,
+        address: &dyn Fn(u16) -> String,
+        long_address: &dyn Fn(u16) -> String,
+    ) -> String {
+        match *self {
+            Instruction::SysCall(addr) => format!("SYS {}", address(addr)),
+            Instruction::Clear => "CLS".to_string(),
+            Instruction::Return => "RET".to_string(),
+            Instruction::ScrollDown(rows) => format!("SCD {}", rows),
+            Instruction::ScrollUp(rows) => format!("SCU {}", rows),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowRes => "LOW".to_string(),
+            Instruction::HighRes => "HIGH".to_string(),
+            Instruction::Jump(addr) => format!("JP {}", address(addr)),
+            Instruction::Call(addr) => format!("CALL {}", address(addr)),
+            Instruction::RegEq(x, value) => format!("SE V{:X}, {:#04X}", x, value),
+            Instruction::RegNeq(x, value) => format!("SNE V{:X}, {:#04X}", x, value),
+            Instruction::RegEqReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+            Instruction::SetReg(x, value) => format!("LD V{:X}, {:#04X}", x, value),
+            Instruction::IncReg(x, value) => format!("ADD V{:X}, {:#04X}", x, value),
+            Instruction::RegSetReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::XOr(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftR(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::RevSub(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftL(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::RegNeqReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::SetAddress(addr) => format!("LD I, {}", address(addr)),
+            Instruction::LongSetAddress(addr) => format!("LD I, LONG {}", long_address(addr)),
+            Instruction::JumpOffset(addr) => format!("JP V0, {}", address(addr)),
+            Instruction::Random(x, value) => format!("RND V{:X}, {:#04X}", x, value),
+            Instruction::Draw(x, y, height) => format!("DRW V{:X}, V{:X}, {}", x, y, height),
+            Instruction::KeyEq(x) => format!("SKP V{:X}", x),
+            Instruction::KeyNeq(x) => format!("SKNP V{:X}", x),
+            Instruction::GetDelay(x) => format!("LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => format!("LD V{:X}, K", x),
+            Instruction::SetDelay(x) => format!("LD DT, V{:X}", x),
+            Instruction::SetSound(x) => format!("LD ST, V{:X}", x),
+            Instruction::IncAddress(x) => format!("ADD I, V{:X}", x),
+            Instruction::SpriteAddress(x) => format!("LD F, V{:X}", x),
+            Instruction::RegDump(x) => format!("LD [I], V{:X}", x),
+            Instruction::RegLoad(x) => format!("LD V{:X}, [I]", x),
+            Instruction::BCD(x) => format!("LD B, V{:X}", x),
+            Instruction::BigSpriteAddress(x) => format!("LD HF, V{:X}", x),
+            Instruction::SaveFlags(x) => format!("LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => format!("LD V{:X}, R", x),
+            Instruction::SelectPlanes(planes) => format!("PLANE {}", planes),
+            Instruction::LoadAudioPattern => "AUDIO".to_string(),
+            Instruction::SetPitch(x) => format!("PITCH V{:X}", x),
         }
     }
 }
+
+/// Classic syntax, with addresses as numbers.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = |address: u16| format!("{:#05X}", address);
+        let long_address = |address: u16| format!("{:#06X}", address);
+        f.write_str(&self.format_classic(&address, &long_address))
+    }
+}
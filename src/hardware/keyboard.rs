@@ -1,15 +1,19 @@
-use winit::event::VirtualKeyCode;
-use winit_input_helper::WinitInputHelper;
-
+/// The hex keypad: which keys are held, and which went down or up during the current frame.
 pub struct Keyboard {
     state: [bool; 16],
+    pressed: [bool; 16],
+    released: [bool; 16],
 }
 
 impl Keyboard {
     pub fn new() -> Self {
         let state = [false; 16];
 
-        Keyboard { state }
+        Keyboard {
+            state,
+            pressed: [false; 16],
+            released: [false; 16],
+        }
     }
 
     pub fn any_key_pressed(&self) -> Option<u8> {
@@ -21,107 +25,71 @@ impl Keyboard {
         None
     }
 
-    pub fn handle_input(&mut self, input: &WinitInputHelper) {
-        if input.key_pressed(VirtualKeyCode::Key1) {
-            self.state[0x1] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::Key2) {
-            self.state[0x2] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::Key3) {
-            self.state[0x3] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::Key4) {
-            self.state[0xC] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::Q) {
-            self.state[0x4] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::W) {
-            self.state[0x5] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::E) {
-            self.state[0x6] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::R) {
-            self.state[0xD] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::A) {
-            self.state[0x7] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::S) {
-            self.state[0x8] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::D) {
-            self.state[0x9] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::F) {
-            self.state[0xE] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::Z) {
-            self.state[0xA] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::X) {
-            self.state[0x0] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::C) {
-            self.state[0xB] = true;
-        }
-        if input.key_pressed(VirtualKeyCode::V) {
-            self.state[0xF] = true;
-        }
+    /// The keys that went down during this frame, even if they've since been let go.
+    pub fn newly_pressed(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16).filter(move |&key| self.was_pressed(key))
+    }
 
-        if input.key_released(VirtualKeyCode::Key1) {
-            self.state[0x1] = false;
-        }
-        if input.key_released(VirtualKeyCode::Key2) {
-            self.state[0x2] = false;
-        }
-        if input.key_released(VirtualKeyCode::Key3) {
-            self.state[0x3] = false;
-        }
-        if input.key_released(VirtualKeyCode::Key4) {
-            self.state[0xC] = false;
-        }
-        if input.key_released(VirtualKeyCode::Q) {
-            self.state[0x4] = false;
-        }
-        if input.key_released(VirtualKeyCode::W) {
-            self.state[0x5] = false;
-        }
-        if input.key_released(VirtualKeyCode::E) {
-            self.state[0x6] = false;
-        }
-        if input.key_released(VirtualKeyCode::R) {
-            self.state[0xD] = false;
-        }
-        if input.key_released(VirtualKeyCode::A) {
-            self.state[0x7] = false;
-        }
-        if input.key_released(VirtualKeyCode::S) {
-            self.state[0x8] = false;
-        }
-        if input.key_released(VirtualKeyCode::D) {
-            self.state[0x9] = false;
-        }
-        if input.key_released(VirtualKeyCode::F) {
-            self.state[0xE] = false;
-        }
-        if input.key_released(VirtualKeyCode::Z) {
-            self.state[0xA] = false;
-        }
-        if input.key_released(VirtualKeyCode::X) {
-            self.state[0x0] = false;
-        }
-        if input.key_released(VirtualKeyCode::C) {
-            self.state[0xB] = false;
-        }
-        if input.key_released(VirtualKeyCode::V) {
-            self.state[0xF] = false;
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key as usize;
+        if self.state[key] != pressed {
+            if pressed {
+                self.pressed[key] = true;
+            } else {
+                self.released[key] = true;
+            }
         }
+        self.state[key] = pressed;
     }
 
     pub fn get_key(&self, key: u8) -> bool {
         self.state[key as usize]
     }
+
+    /// Whether the key went down during this frame, even if it's since been let go.
+    pub fn was_pressed(&self, key: u8) -> bool {
+        self.pressed[key as usize]
+    }
+
+    /// Whether the key went up during this frame, even if it's since been pressed again.
+    pub fn was_released(&self, key: u8) -> bool {
+        self.released[key as usize]
+    }
+
+    /// The held keys and this frame's presses and releases, one bit per key.
+    pub(crate) fn to_bits(&self) -> [u16; 3] {
+        let bits = |keys: &[bool; 16]| {
+            keys.iter()
+                .enumerate()
+                .fold(0, |bits, (key, &set)| bits | (set as u16) << key)
+        };
+        [bits(&self.state), bits(&self.pressed), bits(&self.released)]
+    }
+
+    pub(crate) fn from_bits(bits: [u16; 3]) -> Self {
+        let keys = |bits: u16| {
+            let mut keys = [false; 16];
+            for (key, set) in keys.iter_mut().enumerate() {
+                *set = bits & (1 << key) != 0;
+            }
+            keys
+        };
+        Keyboard {
+            state: keys(bits[0]),
+            pressed: keys(bits[1]),
+            released: keys(bits[2]),
+        }
+    }
+
+    /// Forgets this frame's presses and releases, leaving held keys held.
+    pub fn end_frame(&mut self) {
+        self.pressed = [false; 16];
+        self.released = [false; 16];
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
 }
@@ -1,9 +1,20 @@
+mod audio;
 mod cpu;
+mod display;
 mod font;
 mod instruction;
 mod keyboard;
+mod quirks;
+mod random;
+mod state;
 
-pub use cpu::CPU;
-pub use cpu::DISPLAY_HEIGHT;
-pub use cpu::DISPLAY_WIDTH;
+pub use audio::{AudioSink, NullAudio, RecordingAudio, ToneEvent};
+pub use cpu::{AccessKind, MemoryAccess, CPU};
+pub use display::{
+    DisplayTarget, Framebuffer, NullDisplay, Palette, RecordingDisplay, DISPLAY_HEIGHT,
+    DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH,
+};
+pub use instruction::Instruction;
 pub use keyboard::Keyboard;
+pub use quirks::Quirks;
+pub(crate) use state::{StateReader, StateWriter};
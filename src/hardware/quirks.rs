@@ -0,0 +1,154 @@
+/// Behaviours that differ between CHIP-8 interpreters.
+///
+/// Several opcodes were implemented differently over the years and ROMs are written against
+/// whichever interpreter their author used, so the choice has to be made per ROM. The
+/// `Default`, with every quirk off, is the behaviour this emulator has always had, apart from
+/// `FX0A` waiting for a key to be released; the named presets match the platforms ROMs are
+/// usually written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY into VX rather than shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// `FX55`/`FX65` leave I pointing past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// With `load_store_increments_i`, I is left on the last register rather than past it.
+    pub load_store_increments_i_by_x: bool,
+    /// `BNNN` adds VX, where X is the top nibble of the address, instead of V0.
+    pub jump_uses_vx: bool,
+    /// `8XY1`/`8XY2`/`8XY3` clear VF.
+    pub vf_reset: bool,
+    /// Sprites are cut off at the edges of the screen instead of wrapping around.
+    pub clip_sprites: bool,
+    /// `DXYN` waits for the next 60Hz tick before drawing, limiting it to once per frame.
+    pub display_wait: bool,
+    /// `CXNN` uses a simple 8-bit generator that mixes bytes of memory into its seed, rather
+    /// than SplitMix64.
+    pub memory_random: bool,
+    /// `FX0A` takes whichever key is held straight away, rather than waiting for a key to be
+    /// pressed and released.
+    pub wait_key_on_press: bool,
+    /// 64 KiB of memory, as XO-CHIP has, rather than 4 KiB.
+    pub extended_memory: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            memory_random: false,
+            wait_key_on_press: false,
+            extended_memory: false,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            memory_random: false,
+            wait_key_on_press: false,
+            extended_memory: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            memory_random: false,
+            wait_key_on_press: false,
+            extended_memory: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+            memory_random: false,
+            wait_key_on_press: false,
+            extended_memory: true,
+        }
+    }
+
+    /// How many quirks `to_bits` packs. Files that store the bits store this with them, so
+    /// quirks added later read back as off.
+    pub(crate) const COUNT: u8 = 10;
+
+    /// Every quirk packed into a word, one bit each, for recordings. Quirks are added at the
+    /// end so older files keep their meaning.
+    pub(crate) fn to_bits(self) -> u32 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_uses_vx,
+            self.vf_reset,
+            self.clip_sprites,
+            self.display_wait,
+            self.memory_random,
+            self.wait_key_on_press,
+            self.load_store_increments_i_by_x,
+            self.extended_memory,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &set)| bits | (set as u32) << i)
+    }
+
+    /// Unpacks `to_bits`, or `None` if a bit is set for a quirk this version doesn't have.
+    pub(crate) fn from_bits(bits: u32) -> Option<Self> {
+        if bits >> Quirks::COUNT != 0 {
+            return None;
+        }
+        let bit = |i: u8| bits & (1 << i) != 0;
+        Some(Quirks {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            jump_uses_vx: bit(2),
+            vf_reset: bit(3),
+            clip_sprites: bit(4),
+            display_wait: bit(5),
+            memory_random: bit(6),
+            wait_key_on_press: bit(7),
+            load_store_increments_i_by_x: bit(8),
+            extended_memory: bit(9),
+        })
+    }
+
+    /// Looks up a preset by name, e.g. from a command line flag.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Quirks::default()),
+            "vip" | "cosmac-vip" | "chip8" | "chip-8" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::superchip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use super::state::{StateReader, StateWriter};
+use crate::EmulatorError;
+
+/// The random numbers behind `CXNN`, owned by the machine so a run can be repeated from its
+/// seed and carried over in save states.
+///
+/// Numbers normally come from SplitMix64. With the `memory_random` quirk they come from a
+/// simple 8-bit generator instead, which steps a counter through the first page of memory
+/// and mixes the byte it finds into a one byte seed. It isn't a copy of any interpreter's
+/// routine, just a cheaper and more predictable source of numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RandomGenerator {
+    state: u64,
+    memory_counter: u8,
+    memory_seed: u8,
+}
+
+impl RandomGenerator {
+    pub fn new(seed: u64) -> Self {
+        RandomGenerator {
+            state: seed,
+            memory_counter: seed as u8,
+            memory_seed: (seed >> 8) as u8,
+        }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    /// The 8-bit generator, reading from the first page of `memory`.
+    pub fn next_memory_byte(&mut self, memory: &[u8]) -> u8 {
+        self.memory_counter = self.memory_counter.wrapping_add(1);
+        let byte = memory[self.memory_counter as usize];
+        self.memory_seed = self
+            .memory_seed
+            .rotate_right(1)
+            .wrapping_add(byte)
+            .wrapping_add(self.memory_counter);
+        self.memory_seed
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.state.to_le_bytes());
+        state.u8(self.memory_counter);
+        state.u8(self.memory_seed);
+    }
+
+    pub fn load(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        Ok(RandomGenerator {
+            state: u64::from_le_bytes(state.array()?),
+            memory_counter: state.u8()?,
+            memory_seed: state.u8()?,
+        })
+    }
+}
@@ -1,5 +1,380 @@
-use chip8_emulator::run;
+use std::{env, fs, path::PathBuf, process};
+
+use chip8_emulator::hardware::RecordingDisplay;
+
+use chip8_emulator::trace::{self, TraceFormat};
+use chip8_emulator::{assemble_file, disassemble, octo, png, Movie, Syntax};
+#[cfg(feature = "frontend")]
+use chip8_emulator::{run, Keymap, Options, Quirks, MAX_INSTRUCTIONS_PER_FRAME};
+
+#[cfg(feature = "frontend")]
+const USAGE: &str = "Usage: chip8 [options] <rom>
+       chip8 disasm [--syntax classic|octo] <rom>
+       chip8 asm [-o <rom>] <source>
+       chip8 convert-trace [--format text|binary] <input> <output>
+       chip8 --dump-keymap [--layout NAME]
+       chip8 --replay <movie> [--frames N] [--dump-frame <png>]
+
+Options:
+  --quirks vip|chip48|schip|xochip  emulate a particular interpreter's behaviour
+  --ipf N                           instructions to run per 60Hz frame, 10 if the ROM
+                                    database doesn't say
+  --rom-db FILE                     look ROMs up in a chip-8-database programs.json
+  --remember                        save --quirks and --ipf as this ROM's settings
+  --wall-clock-timers               count the timers down in real time, not per frame
+  --seed N                          start the random numbers from N to repeat a run
+  --keymap FILE                     read keypad bindings from a JSON file
+  --layout qwerty|azerty|dvorak|numpad
+                                    lay the keypad out for another keyboard
+  --record FILE                     record the session's input to a movie file
+  --rewind-seconds N                how far back rewinding can go, 0 to turn it off
+  --debug                           start paused with a debugger console on stdin
+  --trace FILE                      record every instruction run to FILE
+  --trace-format text|binary        the format of the trace, text by default";
+#[cfg(not(feature = "frontend"))]
+const USAGE: &str = "Usage: chip8 disasm [--syntax classic|octo] <rom>
+       chip8 asm [-o <rom>] <source>
+       chip8 convert-trace [--format text|binary] <input> <output>
+       chip8 --replay <movie> [--frames N] [--dump-frame <png>]
+
+Built without the frontend feature, so ROMs can't be run in a window.";
+#[cfg(feature = "frontend")]
+const DEFAULT_REWIND_SECONDS: usize = 10;
+
+#[cfg(feature = "frontend")]
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom_name = None;
+    let mut quirks = None;
+    let mut instructions_per_frame = None;
+    let mut rom_db_file = None;
+    let mut remember = false;
+    let mut wall_clock_timers = false;
+    let mut seed = None;
+    let mut keymap_file = None;
+    let mut layout = None;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut debug = false;
+    let mut record_file = None;
+    let mut trace_file = None;
+    let mut trace_format = TraceFormat::Text;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset name")?;
+                quirks = Some(
+                    Quirks::preset(&name)
+                        .ok_or_else(|| format!("Unknown quirks preset: {}", name))?,
+                );
+            }
+            "--ipf" => {
+                let ipf = args.next().ok_or("--ipf needs a number")?;
+                let ipf = ipf
+                    .parse()
+                    .ok()
+                    .filter(|ipf| (1..=MAX_INSTRUCTIONS_PER_FRAME).contains(ipf))
+                    .ok_or_else(|| {
+                        format!(
+                            "Instructions per frame must be from 1 to {}: {}",
+                            MAX_INSTRUCTIONS_PER_FRAME, ipf
+                        )
+                    })?;
+                instructions_per_frame = Some(ipf);
+            }
+            "--rom-db" => rom_db_file = Some(args.next().ok_or("--rom-db needs a file name")?),
+            "--remember" => remember = true,
+            "--wall-clock-timers" => wall_clock_timers = true,
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid seed: {}", value))?,
+                );
+            }
+            "--keymap" => keymap_file = Some(args.next().ok_or("--keymap needs a file name")?),
+            "--layout" => layout = Some(parse_layout(args.next())?),
+            "--rewind-seconds" => {
+                let seconds = args.next().ok_or("--rewind-seconds needs a number")?;
+                rewind_seconds = seconds
+                    .parse()
+                    .map_err(|_| format!("Invalid number of seconds: {}", seconds))?;
+            }
+            "--record" => record_file = Some(args.next().ok_or("--record needs a file name")?),
+            "--debug" => debug = true,
+            "--trace" => trace_file = Some(args.next().ok_or("--trace needs a file name")?),
+            "--trace-format" => trace_format = parse_trace_format(args.next())?,
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let rom_name = rom_name.ok_or("No file name given for ROM")?;
+    if remember && quirks.is_none() && instructions_per_frame.is_none() {
+        return Err("--remember needs --quirks or --ipf to save".to_string());
+    }
+
+    Ok(Options {
+        rom_name,
+        quirks,
+        instructions_per_frame,
+        rom_db_file,
+        remember,
+        wall_clock_timers,
+        seed,
+        keymap_file,
+        layout,
+        rewind_seconds,
+        debug,
+        record_file,
+        trace_file,
+        trace_format,
+    })
+}
+
+/// Prints a ROM as assembly source.
+fn disasm(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom_name = None;
+    let mut syntax = Syntax::Classic;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                let name = args.next().ok_or("--syntax needs a name")?;
+                syntax =
+                    Syntax::from_name(&name).ok_or_else(|| format!("Unknown syntax: {}", name))?;
+            }
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let rom_name = rom_name.ok_or("No file name given for ROM")?;
+    let rom = fs::read(&rom_name).map_err(|err| format!("Could not read {}: {}", rom_name, err))?;
+    print!("{}", disassemble(&rom, syntax));
+
+    Ok(())
+}
+
+/// Assembles a source file, by default into a ROM next to it. Octo source is compiled.
+fn asm(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().ok_or("-o needs a file name")?)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let source = source.ok_or("No source file given")?;
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+
+    let is_octo = source
+        .extension()
+        .map_or(false, |extension| extension == "8o");
+    let compiled = if is_octo {
+        octo::compile_file(&source)
+    } else {
+        assemble_file(&source)
+    };
+    let rom = match compiled {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    fs::write(&output, rom)
+        .map_err(|err| format!("Could not write {}: {}", output.display(), err))?;
+
+    Ok(())
+}
+
+#[cfg(feature = "frontend")]
+fn parse_layout(name: Option<String>) -> Result<String, String> {
+    let name = name.ok_or("--layout needs a layout name")?;
+    match Keymap::layout(&name) {
+        Some(_) => Ok(name),
+        None => Err(format!("Unknown layout: {}", name)),
+    }
+}
+
+/// Prints the keypad bindings as a keymap file, to start a custom one from.
+#[cfg(feature = "frontend")]
+fn dump_keymap(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut keymap = Keymap::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--layout" => keymap = Keymap::layout(&parse_layout(args.next())?).unwrap(),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    println!("{}", keymap.to_json().pretty());
+
+    Ok(())
+}
+
+/// Plays a movie without a window, optionally saving the last frame shown as a PNG. A fault
+/// in the emulated program is an error, after the frame is saved.
+fn replay(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut movie_file = None;
+    let mut frames = None;
+    let mut dump_frame = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let count = args.next().ok_or("--frames needs a number")?;
+                frames = Some(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid number of frames: {}", count))?,
+                );
+            }
+            "--dump-frame" => {
+                dump_frame = Some(args.next().ok_or("--dump-frame needs a file name")?)
+            }
+            _ if movie_file.is_none() => movie_file = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let movie_file = movie_file.ok_or("No movie file given")?;
+    if let Err(err) = play_movie(&movie_file, frames, dump_frame.as_deref()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Plays the frames of a movie for `replay`. Failing here isn't a usage error, so the
+/// caller reports it on its own.
+fn play_movie(
+    movie_file: &str,
+    frames: Option<usize>,
+    dump_frame: Option<&str>,
+) -> Result<(), String> {
+    let data =
+        fs::read(movie_file).map_err(|err| format!("Could not read {}: {}", movie_file, err))?;
+    let movie = Movie::from_bytes(&data)
+        .map_err(|err| format!("Could not load {}: {}", movie_file, err))?;
+
+    let frames = frames.unwrap_or_else(|| movie.frame_count());
+    if frames > movie.frame_count() {
+        return Err(format!(
+            "{} only has {} frames",
+            movie_file,
+            movie.frame_count()
+        ));
+    }
+
+    let mut chip8 = movie.start().map_err(|err| err.to_string())?;
+    let mut display = RecordingDisplay::new();
+    let fault = (0..frames).find_map(|frame| {
+        movie
+            .play_frame(frame, &mut chip8, &mut display)
+            .err()
+            .map(|err| format!("Emulation stopped in frame {}: {}", frame, err))
+    });
+
+    if let Some(dump_frame) = dump_frame {
+        let image = png::encode_framebuffer(display.frame(), &movie.palette());
+        fs::write(dump_frame, image)
+            .map_err(|err| format!("Could not write {}: {}", dump_frame, err))?;
+    }
+
+    match fault {
+        Some(fault) => Err(fault),
+        None => Ok(()),
+    }
+}
+
+fn parse_trace_format(name: Option<String>) -> Result<TraceFormat, String> {
+    let name = name.ok_or("--trace-format needs a format")?;
+    TraceFormat::from_name(&name).ok_or_else(|| format!("Unknown trace format: {}", name))
+}
+
+/// Rewrites a trace in the other format, or the one given.
+fn convert_trace(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut files = vec![];
+    let mut format = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(parse_trace_format(args.next())?),
+            _ => files.push(arg),
+        }
+    }
+
+    let (input, output) = match files.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err("Expected an input and an output file".to_string()),
+    };
+    let data = fs::read(input).map_err(|err| format!("Could not read {}: {}", input, err))?;
+    let format = format.unwrap_or(if trace::is_binary(&data) {
+        TraceFormat::Text
+    } else {
+        TraceFormat::Binary
+    });
+    let converted = trace::convert(&data, format)?;
+    fs::write(output, converted).map_err(|err| format!("Could not write {}: {}", output, err))?;
+
+    Ok(())
+}
+
+fn exit_with_usage(err: String) -> ! {
+    eprintln!("{}\n{}", err, USAGE);
+    process::exit(1);
+}
 
 fn main() {
-    run();
+    let mut args = env::args().skip(1).peekable();
+
+    let command: Option<fn(_) -> _> = match args.peek().map(String::as_str) {
+        Some("disasm") => Some(disasm),
+        Some("asm") => Some(asm),
+        Some("convert-trace") => Some(convert_trace),
+        #[cfg(feature = "frontend")]
+        Some("--dump-keymap") => Some(dump_keymap),
+        Some("--replay") => Some(replay),
+        _ => None,
+    };
+    if let Some(command) = command {
+        args.next();
+        if let Err(err) = command(args) {
+            exit_with_usage(err);
+        }
+        return;
+    }
+
+    run_rom(args);
+}
+
+/// Runs a ROM in a window, with the options given.
+#[cfg(feature = "frontend")]
+fn run_rom(args: impl Iterator<Item = String>) {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => exit_with_usage(err),
+    };
+
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "frontend"))]
+fn run_rom(mut args: impl Iterator<Item = String>) {
+    let err = match args.next() {
+        Some(arg) => format!("Unknown command: {}", arg),
+        None => "No command given".to_string(),
+    };
+    exit_with_usage(err);
 }
@@ -0,0 +1,310 @@
+//! Recordings of a session's input, which replay it exactly.
+//!
+//! A movie holds everything a run depends on: the ROM, the random seed, the quirks, and for
+//! every frame the speed and the state of the keypad. Timers count frames, so playing the
+//! frames back into a fresh machine repeats the session bit for bit. The palette the session
+//! was shown in is kept too, so frames can be rendered as they looked.
+//!
+//! Movies are saved as `.c8m` files: a `C8MV` header and version, the seed, the number of
+//! quirks and a word with a bit for each, the palette, the ROM's length and data, then eight
+//! bytes per frame.
+
+use crate::hardware::{DisplayTarget, Keyboard, Palette, Quirks, StateReader, StateWriter};
+use crate::{Chip8, EmulatorError, Scheduler};
+
+const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+const MOVIE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    instructions_per_frame: u16,
+    keys: [u16; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    seed: u64,
+    quirks: Quirks,
+    palette: Palette,
+    rom: Vec<u8>,
+    frames: Vec<Frame>,
+}
+
+impl Movie {
+    /// An empty recording of a machine started with these settings.
+    pub fn new(rom: &[u8], seed: u64, quirks: Quirks, palette: Palette) -> Self {
+        Movie {
+            seed,
+            quirks,
+            palette,
+            rom: rom.to_vec(),
+            frames: vec![],
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Records the input to the next frame. Call just before the scheduler runs it.
+    pub fn record_frame(&mut self, keyboard: &Keyboard, scheduler: &Scheduler) {
+        self.frames.push(Frame {
+            instructions_per_frame: scheduler.instructions_per_frame() as u16,
+            keys: keyboard.to_bits(),
+        });
+    }
+
+    /// A machine in the state the recording started from.
+    pub fn start(&self) -> Result<Chip8, EmulatorError> {
+        let mut chip8 = Chip8::headless(self.quirks);
+        chip8.set_seed(self.seed);
+        chip8.load_rom(&self.rom)?;
+        Ok(chip8)
+    }
+
+    /// Runs a recorded frame, with the keypad held as it was. Frames have to be played in
+    /// order, starting from `start`; asking for one past the end is an error.
+    pub fn play_frame(
+        &self,
+        index: usize,
+        chip8: &mut Chip8,
+        display: &mut dyn DisplayTarget,
+    ) -> Result<(), EmulatorError> {
+        let frame = self
+            .frames
+            .get(index)
+            .ok_or(EmulatorError::NoSuchMovieFrame {
+                index,
+                frames: self.frames.len(),
+            })?;
+        *chip8.keyboard_mut() = Keyboard::from_bits(frame.keys);
+        Scheduler::new(frame.instructions_per_frame as usize).run_frame(chip8, display)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = StateWriter::new();
+        data.bytes(MOVIE_MAGIC);
+        data.u16(MOVIE_VERSION);
+        data.bytes(&self.seed.to_le_bytes());
+        data.u8(Quirks::COUNT);
+        data.bytes(&self.quirks.to_bits().to_le_bytes());
+        for color in &self.palette.0 {
+            data.bytes(color);
+        }
+        data.bytes(&(self.rom.len() as u32).to_le_bytes());
+        data.bytes(&self.rom);
+        for frame in &self.frames {
+            data.u16(frame.instructions_per_frame);
+            for &keys in &frame.keys {
+                data.u16(keys);
+            }
+        }
+        data.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EmulatorError> {
+        let body = data
+            .strip_prefix(&MOVIE_MAGIC[..])
+            .ok_or(EmulatorError::InvalidMovie("not a movie file"))?;
+        let mut data = StateReader::new(body);
+        let read = |data: &mut StateReader| -> Result<Self, EmulatorError> {
+            let version = data.u16()?;
+            if version != MOVIE_VERSION {
+                return Err(EmulatorError::UnsupportedMovieVersion(version));
+            }
+            let seed = u64::from_le_bytes(data.array()?);
+            let quirk_count = data.u8()?;
+            let quirk_bits = u32::from_le_bytes(data.array()?);
+            let quirks = Some(quirk_bits)
+                .filter(|bits| quirk_count >= 32 || bits >> quirk_count == 0)
+                .and_then(Quirks::from_bits)
+                .ok_or(EmulatorError::InvalidMovie(
+                    "uses quirks this version doesn't know",
+                ))?;
+            let palette = Palette([data.array()?, data.array()?, data.array()?, data.array()?]);
+            let rom_len = u32::from_le_bytes(data.array()?) as usize;
+            let rom = data.bytes(rom_len)?.to_vec();
+
+            let mut frames = vec![];
+            while !data.is_empty() {
+                frames.push(Frame {
+                    instructions_per_frame: data.u16()?,
+                    keys: [data.u16()?, data.u16()?, data.u16()?],
+                });
+            }
+            Ok(Movie {
+                seed,
+                quirks,
+                palette,
+                rom,
+                frames,
+            })
+        };
+        read(&mut data).map_err(|err| match err {
+            EmulatorError::InvalidSaveState(_) => EmulatorError::InvalidMovie("file ends early"),
+            err => err,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::RecordingDisplay;
+
+    /// Draws a random digit wherever keys 4 and 6 move it, so a replay only matches if the
+    /// seed and every frame's input do.
+    const ROM: &[u8] = &[
+        0xC0, 0x0F, // RND V0, 0x0F
+        0xF0, 0x29, // LD F, V0
+        0x00, 0xE0, // CLS
+        0xD1, 0x25, // DRW V1, V2, 5
+        0x63, 0x04, // LD V3, 4
+        0xE3, 0xA1, // SKNP V3
+        0x71, 0xFF, // ADD V1, -1
+        0x63, 0x06, // LD V3, 6
+        0xE3, 0xA1, // SKNP V3
+        0x71, 0x01, // ADD V1, 1
+        0x12, 0x00, // JP 0x200
+    ];
+
+    fn record(inputs: &[(usize, u8, bool)], frames: usize) -> (Movie, Chip8) {
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8.set_seed(42);
+        chip8.load_rom(ROM).unwrap();
+        let mut movie = Movie::new(ROM, 42, Quirks::default(), Palette::default());
+        let mut scheduler = Scheduler::new(11);
+
+        for frame in 0..frames {
+            for &(_, key, pressed) in inputs.iter().filter(|(at, _, _)| *at == frame) {
+                chip8.keyboard_mut().set_key(key, pressed);
+            }
+            if frame == frames / 2 {
+                scheduler.speed_up();
+            }
+            movie.record_frame(chip8.keyboard(), &scheduler);
+            scheduler
+                .run_frame(&mut chip8, &mut RecordingDisplay::new())
+                .unwrap();
+        }
+        (movie, chip8)
+    }
+
+    #[test]
+    fn replays_exactly() {
+        let inputs = [
+            (3, 0x6, true),
+            (9, 0x6, false),
+            (12, 0x4, true),
+            (20, 0x4, false),
+        ];
+        let (movie, recorded) = record(&inputs, 30);
+
+        let mut chip8 = movie.start().unwrap();
+        let mut display = RecordingDisplay::new();
+        for frame in 0..movie.frame_count() {
+            movie.play_frame(frame, &mut chip8, &mut display).unwrap();
+        }
+
+        assert_eq!(chip8.save_state(), recorded.save_state());
+        assert_eq!(display.frames_drawn(), 30);
+
+        assert_eq!(
+            movie.play_frame(30, &mut chip8, &mut display),
+            Err(EmulatorError::NoSuchMovieFrame {
+                index: 30,
+                frames: 30
+            })
+        );
+    }
+
+    #[test]
+    fn taps_within_a_frame_are_kept() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0x5, true);
+        keyboard.set_key(0x5, false);
+        keyboard.set_key(0xA, true);
+
+        let replayed = Keyboard::from_bits(keyboard.to_bits());
+        assert!(replayed.was_pressed(0x5) && replayed.was_released(0x5));
+        assert!(!replayed.get_key(0x5));
+        assert!(replayed.get_key(0xA));
+    }
+
+    #[test]
+    fn files_load_back() {
+        let (mut movie, _) = record(&[(1, 0x4, true)], 5);
+        movie.quirks = Quirks::cosmac_vip();
+        movie.palette = Palette([[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]]);
+
+        let data = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&data), Ok(movie));
+        assert_eq!(
+            Movie::from_bytes(&data[..data.len() - 3]),
+            Err(EmulatorError::InvalidMovie("file ends early"))
+        );
+        assert_eq!(
+            Movie::from_bytes(b"C8TR"),
+            Err(EmulatorError::InvalidMovie("not a movie file"))
+        );
+
+        let mut newer = data.clone();
+        newer[4] = 3;
+        assert_eq!(
+            Movie::from_bytes(&newer),
+            Err(EmulatorError::UnsupportedMovieVersion(3))
+        );
+    }
+
+    #[test]
+    fn quirks_are_stored_with_their_count() {
+        let (movie, _) = record(&[], 1);
+        let data = movie.to_bytes();
+        // After the magic, version and seed
+        let count = 4 + 2 + 8;
+        assert_eq!(data[count], Quirks::COUNT);
+
+        // A file from before the last quirk was added reads it as off
+        let mut older = data.clone();
+        older[count] = Quirks::COUNT - 1;
+        assert_eq!(Movie::from_bytes(&older), Ok(movie));
+
+        // A bit past the count it was written with is corrupt
+        let mut corrupt = data.clone();
+        corrupt[count] = 1;
+        corrupt[count + 1] = 0b10;
+        assert_eq!(
+            Movie::from_bytes(&corrupt),
+            Err(EmulatorError::InvalidMovie(
+                "uses quirks this version doesn't know"
+            ))
+        );
+
+        // As is one for a quirk added after this version
+        let mut newer = data;
+        newer[count] = Quirks::COUNT + 1;
+        newer[count + 1 + Quirks::COUNT as usize / 8] |= 1 << (Quirks::COUNT % 8);
+        assert_eq!(
+            Movie::from_bytes(&newer),
+            Err(EmulatorError::InvalidMovie(
+                "uses quirks this version doesn't know"
+            ))
+        );
+    }
+}
@@ -0,0 +1,1100 @@
+//! A compiler for Octo, the assembly language used by the Octo IDE.
+//!
+//! Supported are labels (`: name`), `:alias`, `:const`, `:calc`, `:byte`, `:pointer`, `:org`,
+//! `:call`, `:unpack` and `:macro`, every instruction, and the structured `if ... then`,
+//! `if ... begin ... else ... end` and `loop ... while ... again` forms, including the `<`,
+//! `>`, `<=` and `>=` comparisons that are built out of `vF`. As in Octo, `:calc`
+//! expressions have no operator precedence and are evaluated from right to left.
+//!
+//! If a `main` label is defined the program starts with a jump to it, unless it comes first
+//! anyway. `:next`, `:stringmode` and `:assert` aren't supported.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::Path,
+};
+
+use crate::{AsmError, Instruction};
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of an instruction.
+    Address,
+    /// A whole 16 bit word.
+    Long,
+    /// The 12 bit address in the pair of loads made by `:unpack`.
+    Unpack,
+}
+
+struct Fixup {
+    position: usize,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(usize),
+    Value(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Eq(usize, Operand),
+    Ne(usize, Operand),
+    Lt(usize, Operand),
+    Gt(usize, Operand),
+    Le(usize, Operand),
+    Ge(usize, Operand),
+    Key(usize),
+    NotKey(usize),
+}
+
+impl Condition {
+    fn inverse(self) -> Condition {
+        match self {
+            Condition::Eq(x, rhs) => Condition::Ne(x, rhs),
+            Condition::Ne(x, rhs) => Condition::Eq(x, rhs),
+            Condition::Lt(x, rhs) => Condition::Ge(x, rhs),
+            Condition::Ge(x, rhs) => Condition::Lt(x, rhs),
+            Condition::Gt(x, rhs) => Condition::Le(x, rhs),
+            Condition::Le(x, rhs) => Condition::Gt(x, rhs),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    /// Instructions that skip the one after them when the condition doesn't hold.
+    fn skip_unless(self) -> Vec<Instruction> {
+        match self {
+            Condition::Eq(x, Operand::Register(y)) => vec![Instruction::RegNeqReg(x, y)],
+            Condition::Eq(x, Operand::Value(n)) => vec![Instruction::RegNeq(x, n)],
+            Condition::Ne(x, Operand::Register(y)) => vec![Instruction::RegEqReg(x, y)],
+            Condition::Ne(x, Operand::Value(n)) => vec![Instruction::RegEq(x, n)],
+            Condition::Key(x) => vec![Instruction::KeyNeq(x)],
+            Condition::NotKey(x) => vec![Instruction::KeyEq(x)],
+            // The rest compare by subtracting in vF, whose flag is set when there's no borrow
+            Condition::Lt(x, rhs) | Condition::Ge(x, rhs) => {
+                let mut instrs = vec![load_vf(rhs), Instruction::RevSub(0xF, x)];
+                // vF is now set if x >= rhs
+                let false_when = if matches!(self, Condition::Lt(..)) {
+                    1
+                } else {
+                    0
+                };
+                instrs.push(Instruction::RegEq(0xF, false_when));
+                instrs
+            }
+            Condition::Gt(x, rhs) | Condition::Le(x, rhs) => {
+                let mut instrs = vec![load_vf(rhs), Instruction::Sub(0xF, x)];
+                // vF is now set if rhs >= x
+                let false_when = if matches!(self, Condition::Gt(..)) {
+                    1
+                } else {
+                    0
+                };
+                instrs.push(Instruction::RegEq(0xF, false_when));
+                instrs
+            }
+        }
+    }
+}
+
+fn load_vf(operand: Operand) -> Instruction {
+    match operand {
+        Operand::Register(y) => Instruction::RegSetReg(0xF, y),
+        Operand::Value(n) => Instruction::SetReg(0xF, n),
+    }
+}
+
+/// Compiles Octo source held in memory.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    Compiler::new("<input>", source).run()
+}
+
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        message: format!("Could not read {}: {}", name, err),
+    })?;
+    Compiler::new(&name, &source).run()
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register(s: &str) -> Option<usize> {
+    let lower = s.to_ascii_lowercase();
+    let digit = lower.strip_prefix('v')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<Fixup>,
+    // Start addresses of open loops, with the jumps out of them still to fill in
+    loops: Vec<(u16, Vec<usize>)>,
+    // Jumps over the open `begin` and `else` blocks
+    branches: Vec<usize>,
+    // Whether the program starts with a jump to main that hasn't been needed yet
+    main_jump: bool,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Self {
+        Compiler {
+            file: file.to_string(),
+            tokens: tokenize(source),
+            line: 0,
+            rom: vec![],
+            here: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: vec![],
+            loops: vec![],
+            branches: vec![],
+            main_jump: false,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        let tokens: Vec<&Token> = self.tokens.iter().collect();
+        let has_main = tokens
+            .windows(2)
+            .any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        if has_main {
+            self.jump_to_name(Instruction::Jump(0), "main")?;
+            self.main_jump = true;
+        }
+
+        while let Some(token) = self.next_token() {
+            self.statement(token)?;
+        }
+
+        if !self.loops.is_empty() {
+            return Err(self.error("loop is missing its again"));
+        }
+        if !self.branches.is_empty() {
+            return Err(self.error("begin is missing its end"));
+        }
+
+        for fixup in &self.fixups {
+            let address = match self.labels.get(&fixup.name) {
+                Some(&address) => address,
+                None => {
+                    return Err(AsmError {
+                        file: self.file.clone(),
+                        line: fixup.line,
+                        message: format!("Undefined label: {}", fixup.name),
+                    })
+                }
+            };
+            let position = fixup.position;
+            if fixup.kind != FixupKind::Long && address > 0xFFF {
+                return Err(AsmError {
+                    file: self.file.clone(),
+                    line: fixup.line,
+                    message: format!("{} is out of reach, use i := long", fixup.name),
+                });
+            }
+            match fixup.kind {
+                FixupKind::Address => {
+                    self.rom[position] |= (address >> 8) as u8;
+                    self.rom[position + 1] = address as u8;
+                }
+                FixupKind::Unpack => {
+                    self.rom[position + 1] |= (address >> 8) as u8;
+                    self.rom[position + 3] = address as u8;
+                }
+                FixupKind::Long => {
+                    self.rom[position..position + 2].copy_from_slice(&address.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(self.rom)
+    }
+
+    fn next_token(&mut self) -> Option<String> {
+        let token = self.tokens.pop_front()?;
+        self.line = token.line;
+        Some(token.text)
+    }
+
+    fn expect_token(&mut self, what: &str) -> Result<String, AsmError> {
+        self.next_token()
+            .ok_or_else(|| self.error(format!("Expected {} but the file ended", what)))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.expect_token(expected)?;
+        if token != expected {
+            return Err(self.error(format!("Expected {} but found {}", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens
+            .front()
+            .map_or(false, |token| token.text == text)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here >= MEMORY_SIZE {
+            return Err(self.error("Program doesn't fit in memory"));
+        }
+        let position = self.here - PROGRAM_START;
+        if position >= self.rom.len() {
+            self.rom.resize(position + 1, 0);
+        }
+        self.rom[position] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, instr: Instruction) -> Result<(), AsmError> {
+        for byte in instr.encode() {
+            self.emit_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    fn emit_all(&mut self, instrs: Vec<Instruction>) -> Result<(), AsmError> {
+        instrs.into_iter().try_for_each(|instr| self.emit(instr))
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(self.error(format!("{} is already defined", name)));
+        }
+
+        // main coming straight away makes the jump to it pointless
+        if name == "main" && self.main_jump && self.here == PROGRAM_START + 2 {
+            self.rom.clear();
+            self.here = PROGRAM_START;
+            self.fixups.retain(|fixup| fixup.name != "main");
+        }
+        self.main_jump = false;
+
+        self.labels.insert(name, self.here as u16);
+        Ok(())
+    }
+
+    /// Emits an instruction that takes a 12 bit address, filling the address in later if the
+    /// label it names isn't defined yet.
+    fn jump_to(&mut self, instr: Instruction, target: &str) -> Result<(), AsmError> {
+        let known = self.labels.contains_key(target)
+            || self.constants.contains_key(target)
+            || parse_number(target).is_some();
+        if !known {
+            return self.jump_to_name(instr, target);
+        }
+        let address = self.address(target, 0xFFF)?;
+        self.emit(with_address(instr, address))
+    }
+
+    fn jump_to_name(&mut self, instr: Instruction, name: &str) -> Result<(), AsmError> {
+        self.fixups.push(Fixup {
+            position: self.here - PROGRAM_START,
+            name: name.to_string(),
+            kind: FixupKind::Address,
+            line: self.line,
+        });
+        self.emit(with_address(instr, 0))
+    }
+
+    /// Emits a jump whose target is filled in later with `patch_jump`.
+    fn placeholder_jump(&mut self) -> Result<usize, AsmError> {
+        let position = self.here - PROGRAM_START;
+        self.emit(Instruction::Jump(0))?;
+        Ok(position)
+    }
+
+    fn patch_jump(&mut self, position: usize) -> Result<(), AsmError> {
+        if self.here > 0xFFF {
+            return Err(self.error("Jump target is out of reach"));
+        }
+        let jump = Instruction::Jump(self.here as u16).encode();
+        self.rom[position..position + 2].copy_from_slice(&jump);
+        Ok(())
+    }
+
+    fn value(&mut self, token: &str) -> Result<f64, AsmError> {
+        if let Some(value) = parse_number(token) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.constants.get(token) {
+            return Ok(value);
+        }
+        if let Some(&address) = self.labels.get(token) {
+            return Ok(address as f64);
+        }
+        Err(self.error(format!("Unknown name: {}", token)))
+    }
+
+    fn ranged(&mut self, token: &str, min: f64, max: f64, what: &str) -> Result<i64, AsmError> {
+        let value = self.value(token)?.floor();
+        if value < min || value > max {
+            return Err(self.error(format!("{} is out of range for {}", value, what)));
+        }
+        Ok(value as i64)
+    }
+
+    fn byte(&mut self, token: &str) -> Result<u8, AsmError> {
+        Ok(self.ranged(token, -128.0, 255.0, "a byte")? as u8)
+    }
+
+    fn nibble(&mut self, token: &str) -> Result<u8, AsmError> {
+        Ok(self.ranged(token, 0.0, 15.0, "a nibble")? as u8)
+    }
+
+    fn address(&mut self, token: &str, max: u16) -> Result<u16, AsmError> {
+        Ok(self.ranged(token, 0.0, max as f64, "an address")? as u16)
+    }
+
+    fn register(&mut self, token: &str) -> Result<usize, AsmError> {
+        register(token)
+            .or_else(|| self.aliases.get(token).copied())
+            .ok_or_else(|| self.error(format!("Expected a register but found {}", token)))
+    }
+
+    fn next_register(&mut self) -> Result<usize, AsmError> {
+        let token = self.expect_token("a register")?;
+        self.register(&token)
+    }
+
+    fn operand(&mut self, token: &str) -> Result<Operand, AsmError> {
+        match register(token).or_else(|| self.aliases.get(token).copied()) {
+            Some(reg) => Ok(Operand::Register(reg)),
+            None => Ok(Operand::Value(self.byte(token)?)),
+        }
+    }
+
+    fn statement(&mut self, token: String) -> Result<(), AsmError> {
+        match token.as_str() {
+            ":" => {
+                let name = self.expect_token("a label name")?;
+                self.define_label(name)?;
+            }
+            ":alias" => {
+                let name = self.expect_token("an alias name")?;
+                let reg = self.next_register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":const" => {
+                let name = self.expect_token("a constant name")?;
+                let value = self.expect_token("a value")?;
+                let value = self.value(&value)?;
+                if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+                    return Err(self.error(format!("{} is already defined", name)));
+                }
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.expect_token("a constant name")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc()?
+                } else {
+                    let token = self.expect_token("a value")?;
+                    self.value(&token)?
+                };
+                if !(-128.0..=255.0).contains(&value.floor()) {
+                    return Err(self.error(format!("{} is out of range for a byte", value)));
+                }
+                self.emit_byte(value.floor() as i64 as u8)?;
+            }
+            ":pointer" => {
+                let target = self.expect_token("an address")?;
+                let address = match self.labels.get(&target) {
+                    Some(&address) => address,
+                    None if parse_number(&target).is_some()
+                        || self.constants.contains_key(&target) =>
+                    {
+                        self.address(&target, 0xFFFF)?
+                    }
+                    None => {
+                        self.fixups.push(Fixup {
+                            position: self.here - PROGRAM_START,
+                            name: target,
+                            kind: FixupKind::Long,
+                            line: self.line,
+                        });
+                        0
+                    }
+                };
+                let [high, low] = address.to_be_bytes();
+                self.emit_byte(high)?;
+                self.emit_byte(low)?;
+            }
+            ":org" => {
+                let token = self.expect_token("an address")?;
+                let address = self.address(&token, 0xFFFF)? as usize;
+                if address < PROGRAM_START {
+                    return Err(self.error("Programs start at 0x200"));
+                }
+                self.here = address;
+            }
+            ":call" => {
+                let target = self.expect_token("an address")?;
+                self.jump_to(Instruction::Call(0), &target)?;
+            }
+            ":unpack" => {
+                let nibble = self.expect_token("a nibble")?;
+                let nibble = self.nibble(&nibble)? as u16;
+                let target = self.expect_token("a label")?;
+                let known = self.labels.contains_key(&target)
+                    || self.constants.contains_key(&target)
+                    || parse_number(&target).is_some();
+                let address = if known {
+                    self.address(&target, 0xFFF)?
+                } else {
+                    self.fixups.push(Fixup {
+                        position: self.here - PROGRAM_START,
+                        name: target,
+                        kind: FixupKind::Unpack,
+                        line: self.line,
+                    });
+                    0
+                };
+                let packed = nibble << 12 | address;
+                self.emit(Instruction::SetReg(0, (packed >> 8) as u8))?;
+                self.emit(Instruction::SetReg(1, packed as u8))?;
+            }
+            ":macro" => self.define_macro()?,
+            ":breakpoint" => {
+                self.expect_token("a breakpoint name")?;
+            }
+            ":monitor" => {
+                self.expect_token("an address")?;
+                self.expect_token("a length or format")?;
+            }
+            ":next" | ":stringmode" | ":assert" => {
+                return Err(self.error(format!("{} isn't supported", token)))
+            }
+            "clear" => self.emit(Instruction::Clear)?,
+            "return" | ";" => self.emit(Instruction::Return)?,
+            "exit" => self.emit(Instruction::Exit)?,
+            "lores" => self.emit(Instruction::LowRes)?,
+            "hires" => self.emit(Instruction::HighRes)?,
+            "scroll-down" | "scroll-up" => {
+                let rows = self.expect_token("a number of rows")?;
+                let rows = self.nibble(&rows)?;
+                self.emit(if token == "scroll-down" {
+                    Instruction::ScrollDown(rows)
+                } else {
+                    Instruction::ScrollUp(rows)
+                })?;
+            }
+            "scroll-right" => self.emit(Instruction::ScrollRight)?,
+            "scroll-left" => self.emit(Instruction::ScrollLeft)?,
+            "audio" => self.emit(Instruction::LoadAudioPattern)?,
+            "plane" => {
+                let planes = self.expect_token("a plane mask")?;
+                let planes = self.nibble(&planes)?;
+                self.emit(Instruction::SelectPlanes(planes))?;
+            }
+            "bcd" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::BCD(x))?;
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                if self.peek_is("-") {
+                    self.next_token();
+                    let y = self.next_register()?;
+                    self.emit(if token == "save" {
+                        Instruction::SaveRange(x, y)
+                    } else {
+                        Instruction::LoadRange(x, y)
+                    })?;
+                } else {
+                    self.emit(if token == "save" {
+                        Instruction::RegDump(x)
+                    } else {
+                        Instruction::RegLoad(x)
+                    })?;
+                }
+            }
+            "saveflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::SaveFlags(x))?;
+            }
+            "loadflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::LoadFlags(x))?;
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let height = self.expect_token("a sprite height")?;
+                let height = self.nibble(&height)?;
+                self.emit(Instruction::Draw(x, y, height))?;
+            }
+            "jump" => {
+                let target = self.expect_token("an address")?;
+                self.jump_to(Instruction::Jump(0), &target)?;
+            }
+            "jump0" => {
+                let target = self.expect_token("an address")?;
+                self.jump_to(Instruction::JumpOffset(0), &target)?;
+            }
+            "native" => {
+                let target = self.expect_token("an address")?;
+                self.jump_to(Instruction::SysCall(0), &target)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                self.emit(match token.as_str() {
+                    "delay" => Instruction::SetDelay(x),
+                    "buzzer" => Instruction::SetSound(x),
+                    _ => Instruction::SetPitch(x),
+                })?;
+            }
+            "i" => self.address_register()?,
+            "if" => self.conditional()?,
+            "else" => {
+                let skip_else = self.placeholder_jump()?;
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error("else without begin"))?;
+                self.patch_jump(branch)?;
+                self.branches.push(skip_else);
+            }
+            "end" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error("end without begin"))?;
+                self.patch_jump(branch)?;
+            }
+            "loop" => self.loops.push((self.here as u16, vec![])),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error("while outside of a loop"));
+                }
+                let condition = self.condition()?;
+                self.emit_all(condition.inverse().skip_unless())?;
+                let exit = self.placeholder_jump()?;
+                if let Some((_, exits)) = self.loops.last_mut() {
+                    exits.push(exit);
+                }
+            }
+            "again" => {
+                let (start, exits) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| self.error("again without loop"))?;
+                self.emit(Instruction::Jump(start))?;
+                for exit in exits {
+                    self.patch_jump(exit)?;
+                }
+            }
+            _ if register(&token).is_some() || self.aliases.contains_key(&token) => {
+                let x = self.register(&token)?;
+                self.register_operation(x)?;
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ if parse_number(&token).is_some() || self.constants.contains_key(&token) => {
+                let byte = self.byte(&token)?;
+                self.emit_byte(byte)?;
+            }
+            _ => self.jump_to(Instruction::Call(0), &token)?,
+        }
+
+        Ok(())
+    }
+
+    fn address_register(&mut self) -> Result<(), AsmError> {
+        let op = self.expect_token("an operator")?;
+        match op.as_str() {
+            ":=" => {
+                let value = self.expect_token("a value")?;
+                match value.as_str() {
+                    "hex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::SpriteAddress(x))
+                    }
+                    "bighex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::BigSpriteAddress(x))
+                    }
+                    "long" => {
+                        let target = self.expect_token("an address")?;
+                        if self.labels.contains_key(&target)
+                            || self.constants.contains_key(&target)
+                            || parse_number(&target).is_some()
+                        {
+                            let address = self.address(&target, 0xFFFF)?;
+                            self.emit(Instruction::LongSetAddress(address))
+                        } else {
+                            self.fixups.push(Fixup {
+                                position: self.here - PROGRAM_START + 2,
+                                name: target,
+                                kind: FixupKind::Long,
+                                line: self.line,
+                            });
+                            self.emit(Instruction::LongSetAddress(0))
+                        }
+                    }
+                    _ => self.jump_to(Instruction::SetAddress(0), &value),
+                }
+            }
+            "+=" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::IncAddress(x))
+            }
+            _ => Err(self.error(format!("Unknown operator for i: {}", op))),
+        }
+    }
+
+    fn register_operation(&mut self, x: usize) -> Result<(), AsmError> {
+        let op = self.expect_token("an operator")?;
+        let rhs = self.expect_token("a value")?;
+
+        let instr = match (op.as_str(), rhs.as_str()) {
+            (":=", "random") => {
+                let mask = self.expect_token("a mask")?;
+                Instruction::Random(x, self.byte(&mask)?)
+            }
+            (":=", "key") => Instruction::WaitKey(x),
+            (":=", "delay") => Instruction::GetDelay(x),
+            _ => match (op.as_str(), self.operand(&rhs)?) {
+                (":=", Operand::Register(y)) => Instruction::RegSetReg(x, y),
+                (":=", Operand::Value(n)) => Instruction::SetReg(x, n),
+                ("+=", Operand::Register(y)) => Instruction::Add(x, y),
+                ("+=", Operand::Value(n)) => Instruction::IncReg(x, n),
+                ("-=", Operand::Register(y)) => Instruction::Sub(x, y),
+                ("-=", Operand::Value(n)) => Instruction::IncReg(x, n.wrapping_neg()),
+                ("=-", Operand::Register(y)) => Instruction::RevSub(x, y),
+                ("|=", Operand::Register(y)) => Instruction::Or(x, y),
+                ("&=", Operand::Register(y)) => Instruction::And(x, y),
+                ("^=", Operand::Register(y)) => Instruction::XOr(x, y),
+                (">>=", Operand::Register(y)) => Instruction::ShiftR(x, y),
+                ("<<=", Operand::Register(y)) => Instruction::ShiftL(x, y),
+                _ => return Err(self.error(format!("Invalid operation: v{:x} {} {}", x, op, rhs))),
+            },
+        };
+
+        self.emit(instr)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.next_register()?;
+        let op = self.expect_token("a comparison")?;
+
+        let condition = match op.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            _ => {
+                let rhs = self.expect_token("a value")?;
+                let rhs = self.operand(&rhs)?;
+                match op.as_str() {
+                    "==" => Condition::Eq(x, rhs),
+                    "!=" => Condition::Ne(x, rhs),
+                    "<" => Condition::Lt(x, rhs),
+                    ">" => Condition::Gt(x, rhs),
+                    "<=" => Condition::Le(x, rhs),
+                    ">=" => Condition::Ge(x, rhs),
+                    _ => return Err(self.error(format!("Unknown comparison: {}", op))),
+                }
+            }
+        };
+
+        Ok(condition)
+    }
+
+    fn conditional(&mut self) -> Result<(), AsmError> {
+        let condition = self.condition()?;
+        let form = self.expect_token("then or begin")?;
+
+        match form.as_str() {
+            "then" => self.emit_all(condition.skip_unless()),
+            "begin" => {
+                self.emit_all(condition.inverse().skip_unless())?;
+                let branch = self.placeholder_jump()?;
+                self.branches.push(branch);
+                Ok(())
+            }
+            _ => Err(self.error(format!("Expected then or begin but found {}", form))),
+        }
+    }
+
+    /// Reads the tokens of a `{ ... }` block.
+    fn block(&mut self) -> Result<Vec<Token>, AsmError> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = vec![];
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error("{ is missing its }"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.expect_token("a macro name")?;
+        let mut params = vec![];
+        while !self.peek_is("{") {
+            params.push(self.expect_token("{")?);
+        }
+        let body = self.block()?;
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(format!("Too many macro expansions, is {} recursive?", name)));
+        }
+
+        let count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for i in 0..count {
+            let arg = self.expect_token("a macro argument")?;
+            args.insert(self.macros[name].params[i].clone(), arg);
+        }
+
+        let line = self.line;
+        for token in self.macros[name].body.iter().rev() {
+            let text = args.get(&token.text).unwrap_or(&token.text).clone();
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let tokens: Vec<String> = self.block()?.into_iter().map(|token| token.text).collect();
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if position != tokens.len() {
+            return Err(self.error(format!("Unexpected {} in expression", tokens[position])));
+        }
+        Ok(value)
+    }
+
+    fn expression(&mut self, tokens: &[String], position: &mut usize) -> Result<f64, AsmError> {
+        let left = self.term(tokens, position)?;
+        let op = match tokens.get(*position) {
+            Some(op) if op != ")" => op.clone(),
+            _ => return Ok(left),
+        };
+        *position += 1;
+        // Right to left: the whole rest of the expression is the right hand side
+        let right = self.expression(tokens, position)?;
+
+        let (a, b) = (left as i64, right as i64);
+        let value = match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(self.error(format!("Unknown operator: {}", op))),
+        };
+        Ok(value)
+    }
+
+    fn term(&mut self, tokens: &[String], position: &mut usize) -> Result<f64, AsmError> {
+        let token = tokens
+            .get(*position)
+            .ok_or_else(|| self.error("Expression ends early"))?
+            .clone();
+        *position += 1;
+
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| (v == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.term(tokens, position)?));
+        }
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                if tokens.get(*position).map(String::as_str) != Some(")") {
+                    return Err(self.error("( is missing its )"));
+                }
+                *position += 1;
+                Ok(value)
+            }
+            "@" => {
+                let address = self.term(tokens, position)? as usize;
+                let byte = address
+                    .checked_sub(PROGRAM_START)
+                    .and_then(|offset| self.rom.get(offset))
+                    .copied()
+                    .unwrap_or(0);
+                Ok(byte as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self.value(&token),
+        }
+    }
+}
+
+fn with_address(instr: Instruction, address: u16) -> Instruction {
+    match instr {
+        Instruction::Jump(_) => Instruction::Jump(address),
+        Instruction::Call(_) => Instruction::Call(address),
+        Instruction::JumpOffset(_) => Instruction::JumpOffset(address),
+        Instruction::SetAddress(_) => Instruction::SetAddress(address),
+        Instruction::SysCall(_) => Instruction::SysCall(address),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_ok(source: &str) -> Vec<u8> {
+        compile(source).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn compile_err(source: &str) -> (usize, String) {
+        let err = compile(source).unwrap_err();
+        (err.line, err.message)
+    }
+
+    #[test]
+    fn names_and_macros() {
+        let source = "
+            : main
+                :alias counter v3
+                :const SPEED 4
+                :calc DOUBLE { SPEED * 2 }
+                counter := SPEED
+                counter += DOUBLE
+                i := sprite
+                :macro bump reg { reg += 1 }
+                bump v5
+                jump main
+            : sprite
+                0xFF 0x81
+        ";
+        assert_eq!(
+            compile_ok(source),
+            [0x63, 0x04, 0x73, 0x08, 0xA2, 0x0A, 0x75, 0x01, 0x12, 0x00, 0xFF, 0x81]
+        );
+    }
+
+    #[test]
+    fn jumps_to_main_when_it_comes_later() {
+        assert_eq!(compile_ok("0xAA : main ;"), [0x12, 0x03, 0xAA, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn loops() {
+        let source = "
+            loop
+                v0 += 1
+                while v0 != 5
+            again
+        ";
+        assert_eq!(
+            compile_ok(source),
+            [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn if_then() {
+        assert_eq!(
+            compile_ok("if v1 == v2 then v3 := 1 if v1 key then clear"),
+            [0x91, 0x20, 0x63, 0x01, 0xE1, 0xA1, 0x00, 0xE0]
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = "
+            if v0 == 1 begin
+                v1 := 2
+            else
+                v1 := 3
+            end
+        ";
+        assert_eq!(
+            compile_ok(source),
+            [0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0A, 0x61, 0x03]
+        );
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        assert_eq!(
+            compile_ok("if v1 < 5 then v2 := 0"),
+            [0x6F, 0x05, 0x8F, 0x17, 0x3F, 0x01, 0x62, 0x00]
+        );
+        assert_eq!(
+            compile_ok("if v1 > v2 then v2 := 0"),
+            [0x8F, 0x20, 0x8F, 0x15, 0x3F, 0x01, 0x62, 0x00]
+        );
+        assert_eq!(
+            compile_ok("if v1 <= 7 then v2 := 0"),
+            [0x6F, 0x07, 0x8F, 0x15, 0x3F, 0x00, 0x62, 0x00]
+        );
+        assert_eq!(
+            compile_ok("if v1 >= v3 then v2 := 0"),
+            [0x8F, 0x30, 0x8F, 0x17, 0x3F, 0x00, 0x62, 0x00]
+        );
+    }
+
+    #[test]
+    fn unpacks_labels_defined_later() {
+        assert_eq!(
+            compile_ok(":unpack 0xA data : data 0x55"),
+            [0x60, 0xA2, 0x61, 0x04, 0x55]
+        );
+        assert_eq!(
+            compile_ok(": data 0x55 :unpack 0xA data"),
+            [0x55, 0x60, 0xA2, 0x61, 0x00]
+        );
+    }
+
+    #[test]
+    fn names_are_defined_once() {
+        assert_eq!(
+            compile_err(":const X 1\n:const X 2"),
+            (2, "X is already defined".to_string())
+        );
+        assert_eq!(
+            compile_err(": X\n:const X 2"),
+            (2, "X is already defined".to_string())
+        );
+        assert_eq!(
+            compile_err(":const X 1\n: X"),
+            (2, "X is already defined".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_undefined_labels_where_used() {
+        assert_eq!(
+            compile_err("clear\njump nowhere"),
+            (2, "Undefined label: nowhere".to_string())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! A minimal PNG writer, for saving screenshots without an image library.
+//!
+//! Images are stored uncompressed: CHIP-8 screens are small enough that it doesn't matter.
+
+use crate::hardware::{Framebuffer, Palette};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// The most a stored deflate block can hold.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes 8-bit RGB pixels, row by row, as a PNG file.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width as usize * height as usize * 3);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with the filter type, 0 for none
+    let mut scanlines = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks(width as usize * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Draws a frame in the palette's colours, at the screen's current resolution.
+pub fn encode_framebuffer(frame: &Framebuffer, palette: &Palette) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(frame.width() * frame.height() * 3);
+    for y in 0..frame.height() {
+        for x in 0..frame.width() {
+            rgb.extend_from_slice(&palette.color(frame.get_color(x, y)));
+        }
+    }
+    encode(frame.width() as u32, frame.height() as u32, &rgb)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, and a check value that makes the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(is_last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn writes_a_well_formed_file() {
+        let png = encode(2, 1, &[0xFF, 0, 0, 0, 0, 0xFF]);
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+
+        // The image data is one stored block holding both filter bytes and pixels
+        let idat = &png[33..];
+        assert_eq!(&idat[4..8], b"IDAT");
+        let zlib = &idat[8..8 + u32::from_be_bytes([idat[0], idat[1], idat[2], idat[3]]) as usize];
+        assert_eq!(&zlib[..7], &[0x78, 0x01, 1, 7, 0, !7, 0xFF]);
+        assert_eq!(&zlib[7..14], &[0, 0xFF, 0, 0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn splits_large_images_into_blocks() {
+        let data = vec![0x80; 2 * MAX_STORED_BLOCK + 10];
+        let zlib = zlib_stored(&data);
+        // Two full blocks and the remainder, each with a five byte header
+        assert_eq!(zlib.len(), 2 + data.len() + 3 * 5 + 4);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(zlib[2 + 5 + MAX_STORED_BLOCK], 0);
+        assert_eq!(zlib[2 + 2 * (5 + MAX_STORED_BLOCK)], 1);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+
+use crate::scheduler::FRAMES_PER_SECOND;
+use crate::{Chip8, EmulatorError};
+
+/// Differences shorter than this are copied along with the run around them, since a run
+/// costs more to store than a few unchanged bytes.
+const MIN_GAP: usize = 8;
+
+/// A ring buffer of save states taken as the machine runs, so it can be played backwards.
+///
+/// Call `record_frame` after every emulated frame that ran; the oldest snapshots are
+/// dropped once the buffer covers `depth_seconds` of play.
+///
+/// Only the newest snapshot is kept whole. Each older one is stored as the bytes that
+/// differ from the one after it, which for most frames is a handful of registers rather
+/// than all of memory.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    older: VecDeque<Delta>,
+    capacity: usize,
+}
+
+/// Turns one snapshot back into the one taken before it.
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// The changes that turn `from` into `to`.
+    fn between(from: &[u8], to: &[u8]) -> Self {
+        let mut runs: Vec<(usize, Vec<u8>)> = vec![];
+        let mut offset = 0;
+        while offset < to.len() {
+            if from.get(offset) == Some(&to[offset]) {
+                offset += 1;
+                continue;
+            }
+
+            // Extend the run until MIN_GAP bytes in a row are the same again
+            let start = offset;
+            let mut end = offset + 1;
+            let mut same = 0;
+            while end < to.len() && same < MIN_GAP {
+                if from.get(end) == Some(&to[end]) {
+                    same += 1;
+                } else {
+                    same = 0;
+                }
+                end += 1;
+            }
+            let end = end - same;
+            runs.push((start, to[start..end].to_vec()));
+            offset = end;
+        }
+
+        Delta {
+            len: to.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, from: &[u8]) -> Vec<u8> {
+        let mut to = from.to_vec();
+        to.resize(self.len, 0);
+        for (offset, bytes) in &self.runs {
+            to[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        to
+    }
+}
+
+impl Rewind {
+    pub fn new(depth_seconds: usize) -> Self {
+        let capacity = depth_seconds * FRAMES_PER_SECOND;
+
+        Rewind {
+            latest: None,
+            older: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = chip8.save_state();
+        if let Some(previous) = self.latest.take() {
+            self.older.push_back(Delta::between(&state, &previous));
+        }
+        self.latest = Some(state);
+        if self.len() > self.capacity {
+            self.older.pop_front();
+        }
+    }
+
+    /// Steps back to the snapshot before the newest, which is the frame the machine is on,
+    /// returning `false` once there is nothing left to rewind to. The restored snapshot
+    /// becomes the newest, so recording carries on from it.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<bool, EmulatorError> {
+        let (latest, delta) = match (&self.latest, self.older.pop_back()) {
+            (Some(latest), Some(delta)) => (latest, delta),
+            _ => return Ok(false),
+        };
+
+        let previous = delta.apply(latest);
+        if let Err(err) = chip8.load_state(&previous) {
+            self.older.push_back(delta);
+            return Err(err);
+        }
+        self.latest = Some(previous);
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.older.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.older.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::Quirks;
+
+    /// Counts up in V0, calling a subroutine that counts in V1 on the way.
+    const ROM: &[u8] = &[
+        0x70, 0x01, // 200: ADD V0, 1
+        0x22, 0x06, // 202: CALL 0x206
+        0x12, 0x00, // 204: JP 0x200
+        0x71, 0x01, // 206: ADD V1, 1
+        0x00, 0xEE, // 208: RET
+    ];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        chip8
+    }
+
+    /// Runs one pass of the loop per frame, so V0 counts the frames.
+    fn run_frames(chip8: &mut Chip8, rewind: &mut Rewind, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..5 {
+                chip8.step().unwrap();
+            }
+            rewind.record_frame(chip8);
+        }
+    }
+
+    fn frame_count(chip8: &Chip8) -> u8 {
+        chip8.cpu().registers()[0]
+    }
+
+    #[test]
+    fn steps_back_a_frame_at_a_time() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        run_frames(&mut chip8, &mut rewind, 5);
+        assert_eq!(rewind.len(), 5);
+
+        for expected in (1..5).rev() {
+            assert_eq!(rewind.rewind(&mut chip8), Ok(true));
+            assert_eq!(frame_count(&chip8), expected);
+        }
+        // The first frame is as far back as it goes
+        assert_eq!(rewind.rewind(&mut chip8), Ok(false));
+        assert_eq!(frame_count(&chip8), 1);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn carries_on_recording_from_where_it_rewound_to() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        run_frames(&mut chip8, &mut rewind, 10);
+        for _ in 0..3 {
+            rewind.rewind(&mut chip8).unwrap();
+        }
+        assert_eq!(frame_count(&chip8), 7);
+
+        run_frames(&mut chip8, &mut rewind, 2);
+        let mut seen = vec![];
+        while rewind.rewind(&mut chip8).unwrap() {
+            seen.push(frame_count(&chip8));
+        }
+        assert_eq!(seen, [8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_when_full() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(1);
+        run_frames(&mut chip8, &mut rewind, 100);
+        assert_eq!(rewind.len(), FRAMES_PER_SECOND);
+
+        let mut seen = vec![];
+        while rewind.rewind(&mut chip8).unwrap() {
+            seen.push(frame_count(&chip8));
+        }
+        assert_eq!(seen.first(), Some(&99));
+        assert_eq!(seen.last(), Some(&41));
+    }
+
+    #[test]
+    fn restores_every_snapshot_exactly() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        let mut states = vec![];
+        // A step at a time, so the stack depth and with it the state's length changes
+        for _ in 0..40 {
+            chip8.step().unwrap();
+            rewind.record_frame(&chip8);
+            states.push(chip8.save_state());
+        }
+
+        for state in states.iter().rev().skip(1) {
+            assert_eq!(rewind.rewind(&mut chip8), Ok(true));
+            assert_eq!(&chip8.save_state(), state);
+        }
+    }
+
+    #[test]
+    fn does_nothing_when_empty() {
+        let mut chip8 = machine();
+        let state = chip8.save_state();
+        let mut rewind = Rewind::new(10);
+        assert_eq!(rewind.rewind(&mut chip8), Ok(false));
+        assert_eq!(chip8.save_state(), state);
+
+        // Turned off, nothing is kept
+        let mut rewind = Rewind::new(0);
+        run_frames(&mut chip8, &mut rewind, 10);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.rewind(&mut chip8), Ok(false));
+
+        let mut rewind = Rewind::new(10);
+        run_frames(&mut chip8, &mut rewind, 10);
+        rewind.clear();
+        assert_eq!(rewind.len(), 0);
+        assert_eq!(rewind.rewind(&mut chip8), Ok(false));
+    }
+
+    #[test]
+    fn snapshots_after_the_newest_only_keep_changes() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        run_frames(&mut chip8, &mut rewind, 10);
+
+        let stored: usize = rewind
+            .older
+            .iter()
+            .flat_map(|delta| delta.runs.iter())
+            .map(|(_, bytes)| bytes.len())
+            .sum();
+        assert!(stored < 100, "{} bytes", stored);
+    }
+}
@@ -0,0 +1,551 @@
+//! Settings for particular ROMs, looked up by their SHA-1.
+//!
+//! Programs are read from `programs.json` in the format of the community CHIP-8 database
+//! (<https://github.com/chip-8/chip-8-database>): a list of programs, each with a `title`
+//! and its `roms` by hash. A ROM lists the `platforms` it runs on, most suitable first, and
+//! can give a `tickrate`, `quirkyPlatforms` adjusting a platform's quirks, `keys` hints and
+//! `colors`.
+//!
+//! The platforms themselves are built in, giving each one its quirks and default tickrate,
+//! and so is a curated part of the database, from `data/programs.json`. Entries that can't
+//! be read are skipped with a warning rather than failing the whole file.
+//!
+//! Overrides use the same fields, but name a single `platform` and their `quirks` directly:
+//!
+//! ```json
+//! {
+//!   "0123456789abcdef0123456789abcdef01234567": {
+//!     "platform": "superchip",
+//!     "tickrate": 20,
+//!     "quirks": { "wrap": true }
+//!   }
+//! }
+//! ```
+
+use crate::hash;
+use crate::json::Json;
+use crate::{Palette, Quirks};
+
+/// The part of the community database built into the emulator.
+const BUILT_IN_PROGRAMS: &str = include_str!("../data/programs.json");
+
+struct Platform {
+    id: &'static str,
+    name: &'static str,
+    tickrate: usize,
+    quirks: fn() -> Quirks,
+}
+
+/// The platforms the emulator can run, by their ids in the database.
+const PLATFORMS: [Platform; 7] = [
+    Platform {
+        id: "originalChip8",
+        name: "CHIP-8",
+        tickrate: 15,
+        quirks: Quirks::cosmac_vip,
+    },
+    Platform {
+        id: "hybridVIP",
+        name: "CHIP-8 with VIP machine code",
+        tickrate: 15,
+        quirks: Quirks::cosmac_vip,
+    },
+    Platform {
+        id: "modernChip8",
+        name: "Modern CHIP-8",
+        tickrate: 12,
+        quirks: modern_chip8,
+    },
+    Platform {
+        id: "chip48",
+        name: "CHIP-48",
+        tickrate: 30,
+        quirks: Quirks::chip48,
+    },
+    Platform {
+        id: "superchip1",
+        name: "SUPER-CHIP 1.0",
+        tickrate: 30,
+        quirks: superchip1,
+    },
+    Platform {
+        id: "superchip",
+        name: "SUPER-CHIP",
+        tickrate: 30,
+        quirks: Quirks::superchip,
+    },
+    Platform {
+        id: "xochip",
+        name: "XO-CHIP",
+        tickrate: 100,
+        quirks: Quirks::xochip,
+    },
+];
+
+/// SUPER-CHIP 1.0, which still moved I on by X in `FX55`/`FX65` as CHIP-48 did; 1.1 left
+/// it alone.
+fn superchip1() -> Quirks {
+    Quirks {
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: true,
+        ..Quirks::superchip()
+    }
+}
+
+/// What today's interpreters mostly agree on.
+fn modern_chip8() -> Quirks {
+    Quirks {
+        load_store_increments_i: true,
+        ..Quirks::default()
+    }
+}
+
+fn platform(id: &str) -> Option<&'static Platform> {
+    PLATFORMS.iter().find(|platform| platform.id == id)
+}
+
+/// What's known about a ROM.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    /// The database's id for the platform, e.g. `superchip`.
+    pub platform: Option<String>,
+    /// Instructions to run per frame.
+    pub tickrate: Option<usize>,
+    pub quirks: Option<Quirks>,
+    /// What the game uses keypad keys for, e.g. `("up", 0x5)`.
+    pub keys: Vec<(String, u8)>,
+    pub palette: Option<Palette>,
+}
+
+impl RomInfo {
+    /// The platform's name for people, e.g. `SUPER-CHIP`.
+    pub fn platform_name(&self) -> Option<&'static str> {
+        Some(platform(self.platform.as_deref()?)?.name)
+    }
+
+    /// Switches to a platform, taking on its quirks and tickrate.
+    fn set_platform(&mut self, platform: &Platform) {
+        self.platform = Some(platform.id.to_string());
+        self.quirks = Some((platform.quirks)());
+        self.tickrate = Some(platform.tickrate);
+    }
+
+    /// Reads a ROM's entry in the database.
+    fn from_entry(title: Option<&str>, rom: &Json) -> Result<Self, String> {
+        let mut info = RomInfo {
+            title: title.map(str::to_string),
+            ..RomInfo::default()
+        };
+
+        if let Some(platforms) = rom.get("platforms") {
+            let platforms = platforms
+                .as_array()
+                .ok_or("\"platforms\" should be a list")?;
+            let supported = platforms
+                .iter()
+                .filter_map(|id| platform(id.as_str()?))
+                .next();
+            if let Some(platform) = supported {
+                info.set_platform(platform);
+                let quirky = rom
+                    .get("quirkyPlatforms")
+                    .and_then(|quirky| quirky.get(platform.id));
+                if let Some(quirks) = quirky {
+                    info.apply_quirks(quirks)?;
+                }
+            }
+        }
+        info.apply_common(rom)?;
+
+        Ok(info)
+    }
+
+    /// Applies the settings of an override on top.
+    fn apply_override(&mut self, settings: &Json) -> Result<(), String> {
+        if let Some(title) = settings.get("title") {
+            self.title = Some(
+                title
+                    .as_str()
+                    .ok_or("\"title\" should be text")?
+                    .to_string(),
+            );
+        }
+        if let Some(id) = settings.get("platform") {
+            let id = id.as_str().ok_or("\"platform\" should be a name")?;
+            let platform = platform(id).ok_or_else(|| format!("Unknown platform: {}", id))?;
+            self.set_platform(platform);
+        }
+        if let Some(quirks) = settings.get("quirks") {
+            self.apply_quirks(quirks)?;
+        }
+        self.apply_common(settings)
+    }
+
+    /// The fields database entries and overrides share.
+    fn apply_common(&mut self, settings: &Json) -> Result<(), String> {
+        if let Some(tickrate) = settings.get("tickrate") {
+            self.tickrate = Some(
+                tickrate
+                    .as_u64()
+                    .filter(|&tickrate| tickrate > 0)
+                    .ok_or("\"tickrate\" should be a positive whole number")?
+                    as usize,
+            );
+        }
+
+        if let Some(keys) = settings.get("keys") {
+            let keys = keys.as_object().ok_or("\"keys\" should be an object")?;
+            self.keys = keys
+                .iter()
+                .map(|(name, key)| match key.as_u64() {
+                    Some(key) if key < 16 => Ok((name.clone(), key as u8)),
+                    _ => Err(format!("Key {} should be a keypad key from 0 to 15", name)),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        if let Some(pixels) = settings
+            .get("colors")
+            .and_then(|colors| colors.get("pixels"))
+        {
+            let pixels = pixels
+                .as_array()
+                .ok_or("\"pixels\" should be a list of colours")?;
+            let mut palette = self.palette.unwrap_or_default();
+            for (color, pixel) in palette.0.iter_mut().zip(pixels) {
+                *color = pixel
+                    .as_str()
+                    .and_then(parse_color)
+                    .ok_or_else(|| format!("Invalid colour: {}", pixel))?;
+            }
+            self.palette = Some(palette);
+        }
+
+        Ok(())
+    }
+
+    /// Adjusts the quirks by their names in the database, starting from the defaults if
+    /// there's no platform. Only the quirks named change.
+    ///
+    /// As in the database, `memoryLeaveIUnchanged` wins over `memoryIncrementByX`.
+    fn apply_quirks(&mut self, settings: &Json) -> Result<(), String> {
+        let settings = settings
+            .as_object()
+            .ok_or("\"quirks\" should be an object")?;
+        let quirks = self.quirks.get_or_insert_with(Quirks::default);
+        for (name, value) in settings {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| format!("Quirk {} should be true or false", name))?;
+            match name.as_str() {
+                "shift" => quirks.shift_uses_vy = !value,
+                "memoryIncrementByX" => quirks.load_store_increments_i_by_x = value,
+                "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !value,
+                "wrap" => quirks.clip_sprites = !value,
+                "jump" => quirks.jump_uses_vx = value,
+                "vblank" => quirks.display_wait = value,
+                "logic" => quirks.vf_reset = value,
+                // Extensions of the emulator's own
+                // `vipRandom` is what earlier versions saved it as
+                "memoryRandom" | "vipRandom" => quirks.memory_random = value,
+                "waitKeyOnPress" => quirks.wait_key_on_press = value,
+                "extendedMemory" => quirks.extended_memory = value,
+                _ => return Err(format!("Unknown quirk: {}", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes quirks with the names `apply_quirks` reads, for saving an override.
+pub fn quirks_to_json(quirks: &Quirks) -> Json {
+    let flags = [
+        ("shift", !quirks.shift_uses_vy),
+        ("memoryIncrementByX", quirks.load_store_increments_i_by_x),
+        ("memoryLeaveIUnchanged", !quirks.load_store_increments_i),
+        ("wrap", !quirks.clip_sprites),
+        ("jump", quirks.jump_uses_vx),
+        ("vblank", quirks.display_wait),
+        ("logic", quirks.vf_reset),
+        ("memoryRandom", quirks.memory_random),
+        ("waitKeyOnPress", quirks.wait_key_on_press),
+        ("extendedMemory", quirks.extended_memory),
+    ];
+    Json::Object(
+        flags
+            .iter()
+            .map(|&(name, value)| (name.to_string(), Json::Bool(value)))
+            .collect(),
+    )
+}
+
+/// A `#RRGGBB` colour.
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Known ROMs by their SHA-1, from database files and overrides loaded in turn. Later
+/// entries for a ROM replace earlier ones, and overrides adjust them.
+#[derive(Debug, Default)]
+pub struct RomDatabase {
+    roms: Vec<(String, RomInfo)>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase::default()
+    }
+
+    /// The curated entries built into the emulator, for other files to add to.
+    pub fn built_in() -> Self {
+        let mut database = RomDatabase::new();
+        database
+            .load(BUILT_IN_PROGRAMS)
+            .expect("the built in ROM database is valid");
+        database
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    fn insert(&mut self, hash: &str, info: RomInfo) {
+        let hash = hash.to_ascii_lowercase();
+        match self.roms.iter_mut().find(|(known, _)| *known == hash) {
+            Some((_, known)) => *known = info,
+            None => self.roms.push((hash, info)),
+        }
+    }
+
+    /// Adds the programs from a `programs.json` file, returning a warning for each ROM that
+    /// was skipped because its entry couldn't be read.
+    pub fn load(&mut self, text: &str) -> Result<Vec<String>, String> {
+        let programs = Json::parse(text)?;
+        let programs = programs
+            .as_array()
+            .ok_or("The database should be a list of programs")?;
+        let mut warnings = vec![];
+        for program in programs {
+            let title = program.get("title").and_then(Json::as_str);
+            let roms = match program.get("roms").and_then(Json::as_object) {
+                Some(roms) => roms,
+                None => continue,
+            };
+            for (hash, rom) in roms {
+                match RomInfo::from_entry(title, rom) {
+                    Ok(info) => self.insert(hash, info),
+                    Err(err) => warnings.push(format!("Skipping ROM {}: {}", hash, err)),
+                }
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Applies an overrides file, an object of settings by ROM hash. As with `load`, ROMs
+    /// whose settings can't be read are skipped with a warning.
+    pub fn load_overrides(&mut self, text: &str) -> Result<Vec<String>, String> {
+        let overrides = Json::parse(text)?;
+        let overrides = overrides
+            .as_object()
+            .ok_or("Overrides should be an object of ROM hashes")?;
+        let mut warnings = vec![];
+        for (hash, settings) in overrides {
+            let mut info = self.get(hash).cloned().unwrap_or_default();
+            match info.apply_override(settings) {
+                Ok(()) => self.insert(hash, info),
+                Err(err) => warnings.push(format!("Skipping ROM {}: {}", hash, err)),
+            }
+        }
+        Ok(warnings)
+    }
+
+    fn get(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(hash))
+            .map(|(_, info)| info)
+    }
+
+    pub fn lookup(&self, rom_hash: &[u8; 20]) -> Option<&RomInfo> {
+        self.get(&hash::to_hex(rom_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PONG: &[u8] = &[0x6A, 0x02, 0x6B, 0x0C];
+
+    fn pong_hash() -> String {
+        hash::to_hex(&hash::sha1(PONG))
+    }
+
+    fn database() -> RomDatabase {
+        let programs = format!(
+            r##"[
+                {{
+                    "title": "Pong",
+                    "roms": {{
+                        "{}": {{
+                            "platforms": ["megachip8", "superchip", "originalChip8"],
+                            "quirkyPlatforms": {{ "superchip": {{ "wrap": true, "shift": false }} }},
+                            "keys": {{ "up": 1, "down": 4 }},
+                            "colors": {{ "pixels": ["#102030", "#FFEEDD"] }}
+                        }}
+                    }}
+                }},
+                {{ "title": "No ROMs" }}
+            ]"##,
+            pong_hash()
+        );
+        let mut database = RomDatabase::new();
+        database.load(&programs).unwrap();
+        database
+    }
+
+    #[test]
+    fn looks_up_roms_by_hash() {
+        let database = database();
+        let info = database.lookup(&hash::sha1(PONG)).unwrap();
+
+        assert_eq!(info.title.as_deref(), Some("Pong"));
+        assert_eq!(info.platform_name(), Some("SUPER-CHIP"));
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(
+            info.quirks,
+            Some(Quirks {
+                clip_sprites: false,
+                shift_uses_vy: true,
+                ..Quirks::superchip()
+            })
+        );
+        assert_eq!(
+            info.keys,
+            vec![("up".to_string(), 1), ("down".to_string(), 4)]
+        );
+        let palette = info.palette.unwrap();
+        assert_eq!(palette.color(0), [0x10, 0x20, 0x30]);
+        assert_eq!(palette.color(1), [0xFF, 0xEE, 0xDD]);
+        assert_eq!(palette.color(2), Palette::default().color(2));
+
+        assert_eq!(database.lookup(&hash::sha1(&[0x00, 0xE0])), None);
+    }
+
+    #[test]
+    fn overrides_adjust_entries() {
+        let mut database = database();
+        let overrides = format!(
+            r#"{{
+                "{}": {{ "platform": "xochip", "tickrate": 500, "quirks": {{ "vblank": true }} }},
+                "00000000000000000000000000000000000000AA": {{ "title": "Mine" }}
+            }}"#,
+            pong_hash().to_uppercase()
+        );
+        database.load_overrides(&overrides).unwrap();
+
+        let info = database.lookup(&hash::sha1(PONG)).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Pong"));
+        assert_eq!(info.platform_name(), Some("XO-CHIP"));
+        assert_eq!(info.tickrate, Some(500));
+        assert_eq!(
+            info.quirks,
+            Some(Quirks {
+                display_wait: true,
+                ..Quirks::xochip()
+            })
+        );
+        assert_eq!(info.keys.len(), 2);
+        assert_eq!(database.len(), 2);
+    }
+
+    #[test]
+    fn saved_quirks_load_back() {
+        let presets = [
+            Quirks::cosmac_vip(),
+            Quirks::chip48(),
+            Quirks::superchip(),
+            Quirks::xochip(),
+            Quirks::default(),
+        ];
+        for &quirks in &presets {
+            let mut info = RomInfo::default();
+            info.apply_quirks(&quirks_to_json(&quirks)).unwrap();
+            assert_eq!(info.quirks, Some(quirks));
+        }
+    }
+
+    #[test]
+    fn skips_bad_entries_with_a_warning() {
+        let warning = |text: &str| {
+            let mut database = RomDatabase::new();
+            let warnings = database.load_overrides(text).unwrap();
+            assert!(database.is_empty());
+            warnings.join("\n")
+        };
+        assert_eq!(
+            warning(r#"{"ab": {"platform": "megachip8"}}"#),
+            "Skipping ROM ab: Unknown platform: megachip8"
+        );
+        assert_eq!(
+            warning(r#"{"ab": {"quirks": {"fast": true}}}"#),
+            "Skipping ROM ab: Unknown quirk: fast"
+        );
+        assert_eq!(
+            warning(r#"{"ab": {"keys": {"up": 16}}}"#),
+            "Skipping ROM ab: Key up should be a keypad key from 0 to 15"
+        );
+        assert_eq!(
+            warning(r##"{"ab": {"colors": {"pixels": ["#12345"]}}}"##),
+            "Skipping ROM ab: Invalid colour: \"#12345\""
+        );
+
+        // The rest of the file still loads
+        let mut database = RomDatabase::new();
+        let warnings = database
+            .load(
+                r#"[
+                    {"title": "Bad", "roms": {"ab": {"tickrate": 0}}},
+                    {"title": "Good", "roms": {"cd": {"tickrate": 20}}}
+                ]"#,
+            )
+            .unwrap();
+        assert_eq!(
+            warnings,
+            ["Skipping ROM ab: \"tickrate\" should be a positive whole number"]
+        );
+        assert_eq!(database.len(), 1);
+        assert_eq!(database.get("cd").unwrap().tickrate, Some(20));
+
+        assert!(RomDatabase::new().load("{}").is_err());
+    }
+
+    #[test]
+    fn built_in_entries_can_be_replaced() {
+        let mut database = RomDatabase::built_in();
+        let built_in = database.len();
+        let programs = format!(
+            r#"[{{"title": "Mine", "roms": {{"{}": {{"platforms": ["superchip1"]}}}}}}]"#,
+            pong_hash()
+        );
+        database.load(&programs).unwrap();
+
+        let info = database.lookup(&hash::sha1(PONG)).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Mine"));
+        assert!(database.len() <= built_in + 1);
+    }
+
+    #[test]
+    fn superchip_1_0_moves_i_on_by_x() {
+        let quirks = (platform("superchip1").unwrap().quirks)();
+        assert!(quirks.load_store_increments_i && quirks.load_store_increments_i_by_x);
+        assert_ne!(quirks, Quirks::superchip());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::hardware::DisplayTarget;
+use crate::{Chip8, EmulatorError};
+
+pub(crate) const FRAMES_PER_SECOND: usize = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+pub const MAX_INSTRUCTIONS_PER_FRAME: usize = 1000;
+
+/// The speeds `speed_up` and `slow_down` move between.
+const SPEEDS: [usize; 14] = [1, 2, 3, 5, 7, 10, 15, 20, 30, 50, 100, 200, 500, 1000];
+
+/// Runs the machine a 60Hz frame at a time, so its speed doesn't depend on how often the host
+/// gets round to it.
+///
+/// Each frame runs a fixed number of instructions, updates the timers once and presents the
+/// screen once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scheduler {
+    instructions_per_frame: usize,
+}
+
+impl Scheduler {
+    /// `instructions_per_frame` is limited to between 1 and `MAX_INSTRUCTIONS_PER_FRAME`.
+    pub fn new(instructions_per_frame: usize) -> Self {
+        Scheduler {
+            instructions_per_frame: instructions_per_frame.clamp(1, MAX_INSTRUCTIONS_PER_FRAME),
+        }
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        *self = Scheduler::new(instructions_per_frame);
+    }
+
+    /// Moves up to the next faster speed, returning the new one.
+    pub fn speed_up(&mut self) -> usize {
+        if let Some(&faster) = SPEEDS
+            .iter()
+            .find(|&&ipf| ipf > self.instructions_per_frame)
+        {
+            self.instructions_per_frame = faster;
+        }
+        self.instructions_per_frame
+    }
+
+    /// Moves down to the next slower speed, returning the new one.
+    pub fn slow_down(&mut self) -> usize {
+        if let Some(&slower) = SPEEDS
+            .iter()
+            .rev()
+            .find(|&&ipf| ipf < self.instructions_per_frame)
+        {
+            self.instructions_per_frame = slower;
+        }
+        self.instructions_per_frame
+    }
+
+    /// Runs one frame and presents it.
+    ///
+    /// The frame ends early if the debugger pauses the machine, an instruction fails or the
+    /// program exits. While the machine is paused nothing runs and the timers stand still,
+    /// but the screen is still presented; a frame cut short by a pause or a failure doesn't
+    /// tick the timers either.
+    pub fn run_frame(
+        &self,
+        chip8: &mut Chip8,
+        display: &mut dyn DisplayTarget,
+    ) -> Result<(), EmulatorError> {
+        self.run_frame_with_ticks(chip8, display, 1)
+    }
+
+    /// Like `run_frame`, but ticks the timers `timer_ticks` times instead of once, e.g. to
+    /// keep them in step with the wall clock when frames are dropped.
+    pub fn run_frame_with_ticks(
+        &self,
+        chip8: &mut Chip8,
+        display: &mut dyn DisplayTarget,
+        timer_ticks: usize,
+    ) -> Result<(), EmulatorError> {
+        let mut result = Ok(());
+        for _ in 0..self.instructions_per_frame {
+            if chip8.is_paused() || chip8.cpu().is_halted() {
+                break;
+            }
+            result = chip8.step();
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if result.is_ok() && !chip8.is_paused() {
+            for _ in 0..timer_ticks {
+                chip8.tick_frame();
+            }
+        }
+        chip8.draw(display);
+
+        result
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::RecordingDisplay;
+    use crate::Quirks;
+
+    /// A machine running an endless loop of `ADD V0, 1`.
+    fn counter() -> Chip8 {
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn runs_a_frame_of_instructions_and_presents_once() {
+        let mut chip8 = counter();
+        let mut display = RecordingDisplay::new();
+        let scheduler = Scheduler::new(20);
+
+        scheduler.run_frame(&mut chip8, &mut display).unwrap();
+        scheduler.run_frame(&mut chip8, &mut display).unwrap();
+
+        assert_eq!(chip8.cpu().registers()[0], 20);
+        assert_eq!(display.frames_drawn(), 2);
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame() {
+        // LD V0, 10; LD DT, V0; then loops forever
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8
+            .load_rom(&[0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04])
+            .unwrap();
+        let mut display = RecordingDisplay::new();
+        let scheduler = Scheduler::new(100);
+
+        for _ in 0..4 {
+            scheduler.run_frame(&mut chip8, &mut display).unwrap();
+        }
+        assert_eq!(chip8.cpu().delay_timer(), 6);
+
+        scheduler
+            .run_frame_with_ticks(&mut chip8, &mut display, 3)
+            .unwrap();
+        assert_eq!(chip8.cpu().delay_timer(), 3);
+    }
+
+    #[test]
+    fn paused_frames_only_present() {
+        let mut chip8 = counter();
+        let mut display = RecordingDisplay::new();
+        chip8.pause();
+
+        Scheduler::default()
+            .run_frame(&mut chip8, &mut display)
+            .unwrap();
+
+        assert_eq!(chip8.cpu().program_counter(), 0x200);
+        assert_eq!(display.frames_drawn(), 1);
+    }
+
+    #[test]
+    fn frames_cut_short_leave_the_timers() {
+        // LD V0, 10; LD DT, V0; then loops forever
+        let rom = [0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04];
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8.load_rom(&rom).unwrap();
+        chip8.debugger_mut().add_breakpoint(0x204, None);
+        let mut display = RecordingDisplay::new();
+
+        Scheduler::new(10)
+            .run_frame(&mut chip8, &mut display)
+            .unwrap();
+        assert!(chip8.is_paused());
+        assert_eq!(chip8.cpu().delay_timer(), 10);
+
+        // LD V0, 10; LD DT, V0; RET with nothing to return to
+        let mut chip8 = Chip8::headless(Quirks::default());
+        chip8
+            .load_rom(&[0x60, 0x0A, 0xF0, 0x15, 0x00, 0xEE])
+            .unwrap();
+        let result = Scheduler::new(10).run_frame(&mut chip8, &mut display);
+        assert_eq!(result, Err(EmulatorError::StackUnderflow { pc: 0x204 }));
+        assert_eq!(chip8.cpu().delay_timer(), 10);
+    }
+
+    #[test]
+    fn speed_changes_stay_in_range() {
+        let mut scheduler = Scheduler::new(0);
+        assert_eq!(scheduler.instructions_per_frame(), 1);
+        assert_eq!(scheduler.slow_down(), 1);
+        assert_eq!(scheduler.speed_up(), 2);
+
+        scheduler.set_instructions_per_frame(12);
+        assert_eq!(scheduler.speed_up(), 15);
+        scheduler.set_instructions_per_frame(12);
+        assert_eq!(scheduler.slow_down(), 10);
+
+        scheduler.set_instructions_per_frame(5000);
+        assert_eq!(
+            scheduler.instructions_per_frame(),
+            MAX_INSTRUCTIONS_PER_FRAME
+        );
+        assert_eq!(scheduler.speed_up(), MAX_INSTRUCTIONS_PER_FRAME);
+    }
+}
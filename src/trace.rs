@@ -0,0 +1,454 @@
+//! Per-instruction execution traces, for comparing runs against each other or against a
+//! reference implementation.
+//!
+//! A trace holds the machine state just before each instruction runs. It can be written
+//! as text, one line per instruction that diffs well:
+//!
+//! ```text
+//! PC=0200 OP=6120 V=00000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00 ; LD V1, 0x20
+//! ```
+//!
+//! or in a compact binary format, and `convert` turns one into the other.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+    str::FromStr,
+};
+
+use crate::hardware::{StateReader, StateWriter, CPU};
+use crate::Instruction;
+
+const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+const TRACE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Some(TraceFormat::Text),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// The state of the machine as an instruction is about to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The second word of a four byte XO-CHIP instruction.
+    pub operand: Option<u16>,
+    pub registers: [u8; 16],
+    pub address_register: u16,
+    pub stack_depth: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    pub fn capture(cpu: &CPU) -> Self {
+        let pc = cpu.program_counter();
+        let word = |address: u16| {
+            let memory = cpu.memory();
+            let high = memory.get(address as usize).copied().unwrap_or(0);
+            let low = memory.get(address as usize + 1).copied().unwrap_or(0);
+            ((high as u16) << 8) | low as u16
+        };
+
+        let opcode = word(pc);
+        let operand = if Instruction::is_long(opcode) {
+            Some(word(pc.wrapping_add(2)))
+        } else {
+            None
+        };
+
+        TraceRecord {
+            pc,
+            opcode,
+            operand,
+            registers: *cpu.registers(),
+            address_register: cpu.address_register(),
+            stack_depth: cpu.stack().len() as u8,
+            delay_timer: cpu.delay_timer(),
+            sound_timer: cpu.sound_timer(),
+        }
+    }
+
+    pub fn instruction(&self) -> Option<Instruction> {
+        match self.operand {
+            Some(operand) => Instruction::decode_long(self.opcode, operand),
+            None => Instruction::decode(self.opcode),
+        }
+    }
+
+    fn opcode_text(&self) -> String {
+        match self.operand {
+            Some(operand) => format!("{:04X}{:04X}", self.opcode, operand),
+            None => format!("{:04X}", self.opcode),
+        }
+    }
+
+    fn write_binary(&self, out: &mut StateWriter) {
+        out.u16(self.pc);
+        out.u16(self.opcode);
+        out.u8(self.operand.is_some() as u8);
+        out.u16(self.operand.unwrap_or(0));
+        out.bytes(&self.registers);
+        out.u16(self.address_register);
+        out.u8(self.stack_depth);
+        out.u8(self.delay_timer);
+        out.u8(self.sound_timer);
+    }
+
+    fn read_binary(data: &mut StateReader) -> Result<Self, String> {
+        let read = |data: &mut StateReader| -> Result<Self, crate::EmulatorError> {
+            let pc = data.u16()?;
+            let opcode = data.u16()?;
+            let has_operand = data.bool()?;
+            let operand = data.u16()?;
+            Ok(TraceRecord {
+                pc,
+                opcode,
+                operand: if has_operand { Some(operand) } else { None },
+                registers: data.array()?,
+                address_register: data.u16()?,
+                stack_depth: data.u8()?,
+                delay_timer: data.u8()?,
+                sound_timer: data.u8()?,
+            })
+        };
+        read(data).map_err(|_| "Trace ends part way through a record".to_string())
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registers: String = self
+            .registers
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+        write!(
+            f,
+            "PC={:04X} OP={} V={} I={:04X} SP={} DT={:02X} ST={:02X} ; ",
+            self.pc,
+            self.opcode_text(),
+            registers,
+            self.address_register,
+            self.stack_depth,
+            self.delay_timer,
+            self.sound_timer
+        )?;
+        match self.instruction() {
+            Some(instr) => write!(f, "{}", instr),
+            None => write!(f, "unknown instruction"),
+        }
+    }
+}
+
+/// Parses a line written by `Display`. Anything after the `;` is ignored, since the
+/// instruction is decoded from the opcode.
+impl FromStr for TraceRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split(';').next().unwrap_or("");
+        // Values are sliced by byte below
+        if !fields.is_ascii() {
+            return Err(format!("Unexpected characters in trace line: {}", s));
+        }
+        let mut record = TraceRecord {
+            pc: 0,
+            opcode: 0,
+            operand: None,
+            registers: [0; 16],
+            address_register: 0,
+            stack_depth: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+
+        let hex = |value: &str| {
+            u16::from_str_radix(value, 16).map_err(|_| format!("Invalid hex value: {}", value))
+        };
+
+        let mut seen = 0;
+        for field in fields.split_whitespace() {
+            let (name, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("Expected NAME=value: {}", field))?;
+            match name {
+                "PC" => record.pc = hex(value)?,
+                "OP" if value.len() == 8 => {
+                    record.opcode = hex(&value[..4])?;
+                    record.operand = Some(hex(&value[4..])?);
+                }
+                "OP" => record.opcode = hex(value)?,
+                "V" if value.len() == 32 => {
+                    for (reg, register) in record.registers.iter_mut().enumerate() {
+                        *register = hex(&value[reg * 2..reg * 2 + 2])? as u8;
+                    }
+                }
+                "V" => return Err(format!("Expected 16 registers: {}", value)),
+                "I" => record.address_register = hex(value)?,
+                "SP" => {
+                    record.stack_depth = value
+                        .parse()
+                        .map_err(|_| format!("Invalid stack depth: {}", value))?
+                }
+                "DT" => record.delay_timer = hex(value)? as u8,
+                "ST" => record.sound_timer = hex(value)? as u8,
+                _ => return Err(format!("Unknown field: {}", name)),
+            }
+            seen += 1;
+        }
+
+        if seen != 7 {
+            return Err(format!("Expected PC, OP, V, I, SP, DT and ST: {}", s));
+        }
+        Ok(record)
+    }
+}
+
+/// Somewhere `Chip8` can send a record of each instruction it runs.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a trace as it's recorded, e.g. to a file.
+///
+/// A write error stops the trace and is returned from the next `flush`.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        TraceWriter {
+            out,
+            format,
+            started: false,
+            error: None,
+        }
+    }
+
+    /// Writes the binary format's header, if it hasn't been already.
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started && self.format == TraceFormat::Binary {
+            let mut header = StateWriter::new();
+            header.bytes(TRACE_MAGIC);
+            header.u16(TRACE_VERSION);
+            self.out.write_all(&header.finish())?;
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.start()?;
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record),
+            TraceFormat::Binary => {
+                let mut data = StateWriter::new();
+                record.write_binary(&mut data);
+                self.out.write_all(&data.finish())
+            }
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.write(record) {
+            self.error = Some(err);
+        }
+    }
+
+    /// Also writes the header of a binary trace that has no records yet, so even an empty
+    /// trace can be read back.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.start()?;
+        self.out.flush()
+    }
+}
+
+/// A trace sink that keeps every record in memory.
+///
+/// Clones share the same records, so keep one around before handing the other to `Chip8`.
+#[derive(Clone, Default)]
+pub struct RecordingTrace {
+    records: Rc<RefCell<Vec<TraceRecord>>>,
+}
+
+impl RecordingTrace {
+    pub fn new() -> Self {
+        RecordingTrace::default()
+    }
+
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.borrow().clone()
+    }
+}
+
+impl TraceSink for RecordingTrace {
+    fn record(&mut self, record: &TraceRecord) {
+        self.records.borrow_mut().push(*record);
+    }
+}
+
+/// Whether the data is a trace in the binary format, rather than text.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(TRACE_MAGIC)
+}
+
+/// Reads a trace in either format.
+pub fn read_trace(data: &[u8]) -> Result<Vec<TraceRecord>, String> {
+    if let Some(body) = data.strip_prefix(&TRACE_MAGIC[..]) {
+        let mut data = StateReader::new(body);
+        let version = data
+            .u16()
+            .map_err(|_| "Trace ends part way through its header".to_string())?;
+        if version != TRACE_VERSION {
+            return Err(format!("Unsupported trace version {}", version));
+        }
+
+        let mut records = vec![];
+        while !data.is_empty() {
+            records.push(TraceRecord::read_binary(&mut data)?);
+        }
+        return Ok(records);
+    }
+
+    let text = std::str::from_utf8(data).map_err(|_| "Not a trace file".to_string())?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            line.parse()
+                .map_err(|err| format!("Line {}: {}", index + 1, err))
+        })
+        .collect()
+}
+
+pub fn write_trace(records: &[TraceRecord], format: TraceFormat) -> Vec<u8> {
+    let mut writer = TraceWriter::new(vec![], format);
+    for record in records {
+        writer.record(record);
+    }
+    writer.flush().expect("writing to memory can't fail");
+    writer.out
+}
+
+/// Converts a trace from one format to the other.
+pub fn convert(data: &[u8], format: TraceFormat) -> Result<Vec<u8>, String> {
+    Ok(write_trace(&read_trace(data)?, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<TraceRecord> {
+        let mut registers = [0; 16];
+        registers[0xF] = 0xAB;
+        vec![
+            TraceRecord {
+                pc: 0x200,
+                opcode: 0x6120,
+                operand: None,
+                registers: [0; 16],
+                address_register: 0,
+                stack_depth: 0,
+                delay_timer: 0,
+                sound_timer: 0,
+            },
+            TraceRecord {
+                pc: 0x202,
+                opcode: 0xF000,
+                operand: Some(0x1234),
+                registers,
+                address_register: 0xFFFF,
+                stack_depth: 16,
+                delay_timer: 0x3C,
+                sound_timer: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn text_lines_parse_back() {
+        let line = records()[1].to_string();
+        assert_eq!(
+            line,
+            "PC=0202 OP=F0001234 V=000000000000000000000000000000AB I=FFFF SP=16 DT=3C ST=01 \
+             ; LD I, LONG 0x1234"
+        );
+        assert_eq!(line.parse(), Ok(records()[1]));
+    }
+
+    #[test]
+    fn traces_round_trip_in_both_formats() {
+        for &format in [TraceFormat::Text, TraceFormat::Binary].iter() {
+            let data = write_trace(&records(), format);
+            assert_eq!(is_binary(&data), format == TraceFormat::Binary);
+            assert_eq!(read_trace(&data), Ok(records()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn converts_between_formats() {
+        let text = write_trace(&records(), TraceFormat::Text);
+        let binary = convert(&text, TraceFormat::Binary).unwrap();
+        assert_eq!(binary, write_trace(&records(), TraceFormat::Binary));
+        assert_eq!(convert(&binary, TraceFormat::Text), Ok(text));
+    }
+
+    #[test]
+    fn empty_binary_traces_have_a_header() {
+        let data = write_trace(&[], TraceFormat::Binary);
+        assert!(is_binary(&data));
+        assert_eq!(read_trace(&data), Ok(vec![]));
+
+        let mut writer = TraceWriter::new(vec![], TraceFormat::Binary);
+        writer.flush().unwrap();
+        assert_eq!(writer.out, data);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let line = records()[0].to_string();
+        // Both are the right length in bytes, but split a character where they're sliced
+        assert!(line
+            .replace("OP=6120", "OP=F00é123")
+            .parse::<TraceRecord>()
+            .is_err());
+        assert!(line.replace("V=00", "V=é").parse::<TraceRecord>().is_err());
+        assert!(line.replace("SP=0 ", "").parse::<TraceRecord>().is_err());
+        assert_eq!(
+            read_trace(b"C8TR\x01\x00\x00"),
+            Err("Trace ends part way through a record".to_string())
+        );
+    }
+}
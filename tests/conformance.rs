@@ -0,0 +1,229 @@
+//! Runs test ROMs headless for a fixed number of frames, through the same `Scheduler` as the
+//! frontend so the timers and the display wait behave as they do there, and compares the
+//! screen they leave against a stored bitmap in `tests/conformance`, one line per row with
+//! `#` for a lit pixel.
+//!
+//! The community test ROMs aren't redistributed with the emulator, so their tests are
+//! ignored by default. Put the ROMs in `tests/conformance/roms` (see the README there) and
+//! run them with `cargo test --test conformance -- --ignored`; a missing ROM or bitmap fails.
+
+use std::{env, fs, path::PathBuf};
+
+use chip8_emulator::hardware::{Framebuffer, NullDisplay};
+use chip8_emulator::{assemble_file, Chip8, Quirks, Scheduler, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Instructions per frame, the frontend's speed for ROMs the database doesn't know.
+const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+struct Case<'a> {
+    /// A `.ch8` ROM in `roms`, or `.asm` source assembled first.
+    rom: &'a str,
+    expected: &'a str,
+    quirks: Quirks,
+    frames: usize,
+    /// Keys pressed and released by frame, e.g. to pick from a menu.
+    inputs: &'a [(usize, u8, bool)],
+}
+
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "conformance", name]
+        .iter()
+        .collect()
+}
+
+fn load(case: &Case) -> Vec<u8> {
+    if case.rom.ends_with(".asm") {
+        return assemble_file(&fixture(case.rom)).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    let path = fixture("roms").join(case.rom);
+    fs::read(&path).unwrap_or_else(|err| {
+        panic!(
+            "{}: {} (see tests/conformance/roms/README.md)",
+            path.display(),
+            err
+        )
+    })
+}
+
+fn run(case: &Case, rom: &[u8]) -> Framebuffer {
+    let mut chip8 = Chip8::headless(case.quirks);
+    chip8.load_rom(rom).unwrap();
+    let scheduler = Scheduler::new(INSTRUCTIONS_PER_FRAME);
+
+    for frame in 0..case.frames {
+        for &(_, key, pressed) in case.inputs.iter().filter(|input| input.0 == frame) {
+            chip8.keyboard_mut().set_key(key, pressed);
+        }
+        if chip8.cpu().is_halted() {
+            break;
+        }
+        if let Err(err) = scheduler.run_frame(&mut chip8, &mut NullDisplay) {
+            panic!("{}: frame {} faulted: {}", case.rom, frame, err);
+        }
+    }
+
+    chip8.cpu().framebuffer().clone()
+}
+
+fn to_bitmap(frame: &Framebuffer) -> String {
+    let mut bitmap = String::new();
+    for y in 0..frame.height() {
+        for x in 0..frame.width() {
+            bitmap.push(if frame.get_pixel(x, y) { '#' } else { '.' });
+        }
+        bitmap.push('\n');
+    }
+    bitmap
+}
+
+/// Marks each pixel of the expected bitmap: `#` and `.` where they agree, `-` for a pixel
+/// that should be lit but isn't and `+` for one lit that shouldn't be.
+fn ascii_diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut diff = String::new();
+    let mut wrong = 0;
+    for (want_row, got_row) in expected.lines().zip(actual.lines()) {
+        for (want, got) in want_row.chars().zip(got_row.chars()) {
+            diff.push(match (want, got) {
+                ('#', '#') => '#',
+                ('#', _) => '-',
+                (_, '#') => '+',
+                _ => '.',
+            });
+            wrong += (want != got) as usize;
+        }
+        diff.push('\n');
+    }
+
+    let (expected_rows, actual_rows) = (expected.lines().count(), actual.lines().count());
+    if expected_rows != actual_rows {
+        diff.push_str(&format!(
+            "screen has {} rows, expected {}\n",
+            actual_rows, expected_rows
+        ));
+    }
+    Some(format!("{} pixels differ\n{}", wrong, diff))
+}
+
+fn check(case: Case) {
+    let rom = load(&case);
+    let actual = to_bitmap(&run(&case, &rom));
+    let path = fixture(case.expected);
+
+    if env::var_os("UPDATE_EXPECTED").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("{}: {} (run with UPDATE_EXPECTED=1?)", case.expected, err));
+    if let Some(diff) = ascii_diff(&expected, &actual) {
+        panic!("{} doesn't match the screen: {}", case.expected, diff);
+    }
+}
+
+#[test]
+fn expected_bitmaps_are_the_standard_screen_size() {
+    for entry in fs::read_dir(fixture("")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(false, |ext| ext == "txt") {
+            let bitmap = fs::read_to_string(&path).unwrap();
+            let rows: Vec<_> = bitmap.lines().collect();
+            assert_eq!(rows.len(), DISPLAY_HEIGHT, "{}", path.display());
+            assert!(
+                rows.iter().all(|row| row.len() == DISPLAY_WIDTH),
+                "{}",
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn opcodes() {
+    check(Case {
+        rom: "opcodes.asm",
+        expected: "opcodes.txt",
+        quirks: Quirks::default(),
+        frames: 100,
+        inputs: &[],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn chip8_logo() {
+    check(Case {
+        rom: "1-chip8-logo.ch8",
+        expected: "chip8-logo.txt",
+        quirks: Quirks::default(),
+        frames: 100,
+        inputs: &[],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn ibm_logo() {
+    check(Case {
+        rom: "2-ibm-logo.ch8",
+        expected: "ibm-logo.txt",
+        quirks: Quirks::default(),
+        frames: 100,
+        inputs: &[],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn corax_opcodes() {
+    check(Case {
+        rom: "3-corax+.ch8",
+        expected: "corax.txt",
+        quirks: Quirks::default(),
+        frames: 500,
+        inputs: &[],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn flags() {
+    check(Case {
+        rom: "4-flags.ch8",
+        expected: "flags.txt",
+        quirks: Quirks::default(),
+        frames: 500,
+        inputs: &[],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn xochip_quirks() {
+    // Picks XO-CHIP from the menu
+    check(Case {
+        rom: "5-quirks.ch8",
+        expected: "quirks-xochip.txt",
+        quirks: Quirks::xochip(),
+        frames: 2000,
+        inputs: &[(10, 0x4, true), (11, 0x4, false)],
+    });
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/conformance/roms"]
+fn keypad_down() {
+    // Picks the EX9E test from the menu, then holds down key A
+    check(Case {
+        rom: "6-keypad.ch8",
+        expected: "keypad-down.txt",
+        quirks: Quirks::default(),
+        frames: 500,
+        inputs: &[(10, 0x1, true), (11, 0x1, false), (100, 0xA, true)],
+    });
+}
@@ -0,0 +1,229 @@
+; Checks the result and flag of each arithmetic instruction and draws a tick for
+; every check that passes or a cross for one that fails, eight to a row in order.
+; In the spirit of the corax+ opcode test, but small enough to keep in the tree.
+;
+; Each check counts what it got right into V0 and calls `result`, which passes it
+; when V0 is 2.
+
+    CLS
+    LD V6, 0x00         ; cursor
+    LD V7, 0x02
+
+    ; ADD with carry
+    LD V1, 0xFF
+    LD V2, 0x01
+    ADD V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x00
+    ADD V0, 0x01
+    SNE V3, 0x01
+    ADD V0, 0x01
+    CALL result
+
+    ; ADD without carry
+    LD V1, 0x10
+    ADD V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x11
+    ADD V0, 0x01
+    SNE V3, 0x00
+    ADD V0, 0x01
+    CALL result
+
+    ; ADD into VF keeps the flag
+    LD VF, 0xFF
+    ADD VF, V2
+    LD V0, 0x00
+    SNE VF, 0x01
+    ADD V0, 0x02
+    CALL result
+
+    ; SUB with borrow
+    LD V1, 0x05
+    LD V2, 0x07
+    SUB V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0xFE
+    ADD V0, 0x01
+    SNE V3, 0x00
+    ADD V0, 0x01
+    CALL result
+
+    ; SUB of equal values doesn't borrow
+    LD V1, 0x07
+    SUB V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x00
+    ADD V0, 0x01
+    SNE V3, 0x01
+    ADD V0, 0x01
+    CALL result
+
+    ; SUBN without borrow
+    LD V1, 0x05
+    SUBN V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x02
+    ADD V0, 0x01
+    SNE V3, 0x01
+    ADD V0, 0x01
+    CALL result
+
+    ; SUBN with borrow
+    LD V1, 0x09
+    SUBN V1, V2
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0xFE
+    ADD V0, 0x01
+    SNE V3, 0x00
+    ADD V0, 0x01
+    CALL result
+
+    ; SUB into VF keeps the flag
+    LD VF, 0x09
+    SUB VF, V2
+    LD V0, 0x00
+    SNE VF, 0x01
+    ADD V0, 0x02
+    CALL result
+
+    ; SHR shifts out the low bit
+    LD V1, 0x81
+    SHR V1
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x40
+    ADD V0, 0x01
+    SNE V3, 0x01
+    ADD V0, 0x01
+    CALL result
+
+    ; SHL shifts out the high bit
+    LD V1, 0x81
+    SHL V1
+    LD V3, VF
+    LD V0, 0x00
+    SNE V1, 0x02
+    ADD V0, 0x01
+    SNE V3, 0x01
+    ADD V0, 0x01
+    CALL result
+
+    ; OR, AND and XOR
+    LD V1, 0x0C
+    LD V2, 0x0A
+    OR V1, V2
+    LD V3, 0x0C
+    AND V3, V2
+    LD V0, 0x00
+    SNE V1, 0x0E
+    ADD V0, 0x01
+    SNE V3, 0x08
+    ADD V0, 0x01
+    CALL result
+
+    LD V1, 0x0C
+    XOR V1, V2
+    LD V0, 0x00
+    SNE V1, 0x06
+    ADD V0, 0x02
+    CALL result
+
+    ; ADD with a constant wraps and leaves VF alone
+    LD VF, 0x55
+    LD V1, 0xF0
+    ADD V1, 0x20
+    LD V0, 0x00
+    SNE V1, 0x10
+    ADD V0, 0x01
+    SNE VF, 0x55
+    ADD V0, 0x01
+    CALL result
+
+    ; BCD of 255
+    LD V1, 0xFF
+    LD I, digits
+    LD B, V1
+    LD V2, [I]
+    LD V3, V0
+    SE V2, 0x05
+    LD V3, 0x00
+    LD V0, 0x00
+    SNE V3, 0x02
+    ADD V0, 0x01
+    SNE V1, 0x05
+    ADD V0, 0x01
+    CALL result
+
+    ; Store and load through I
+    LD V0, 0x12
+    LD V1, 0x34
+    LD I, scratch
+    LD [I], V1
+    LD V0, 0x00
+    LD V1, 0x00
+    LD I, scratch
+    LD V1, [I]
+    LD V2, V0
+    LD V0, 0x00
+    SNE V2, 0x12
+    ADD V0, 0x01
+    SNE V1, 0x34
+    ADD V0, 0x01
+    CALL result
+
+    ; Skips over the next instruction
+    LD V0, 0x00
+    LD V1, 0x03
+    LD V2, 0x03
+    SE V1, V2
+    JP skip_done
+    ADD V0, 0x01
+    SNE V1, V2
+    ADD V0, 0x01
+skip_done:
+    CALL result
+
+    EXIT
+
+; Draws a tick if V0 is 2 or a cross otherwise at the cursor, then moves it on.
+result:
+    LD I, cross
+    SNE V0, 0x02
+    LD I, tick
+    DRW V6, V7, 5
+    ADD V6, 0x08
+    SE V6, 0x40
+    RET
+    LD V6, 0x00
+    ADD V7, 0x07
+    RET
+
+tick:
+sprite
+    ......#.
+    .....#..
+    #...#...
+    .#.#....
+    ..#.....
+end
+
+cross:
+sprite
+    #...#...
+    .#.#....
+    ..#.....
+    .#.#....
+    #...#...
+end
+
+digits:
+    db 0, 0, 0
+scratch:
+    db 0, 0
@@ -0,0 +1,186 @@
+//! Runs small ROMs from `tests/differential` with scripted key presses and compares every
+//! instruction's machine state against a stored golden trace.
+//!
+//! After an intended change in behaviour, regenerate the traces with
+//! `UPDATE_GOLDEN=1 cargo test --test differential` and review the diff.
+
+use std::{env, fs, path::PathBuf};
+
+use chip8_emulator::trace::{self, RecordingTrace, TraceFormat, TraceRecord};
+use chip8_emulator::{assemble_file, Chip8, Quirks};
+
+/// A key going down or up before the given cycle runs.
+struct KeyEvent {
+    cycle: usize,
+    key: u8,
+    pressed: bool,
+}
+
+const fn press(cycle: usize, key: u8) -> KeyEvent {
+    KeyEvent {
+        cycle,
+        key,
+        pressed: true,
+    }
+}
+
+const fn release(cycle: usize, key: u8) -> KeyEvent {
+    KeyEvent {
+        cycle,
+        key,
+        pressed: false,
+    }
+}
+
+struct Case<'a> {
+    rom: &'a str,
+    golden: &'a str,
+    quirks: Quirks,
+    cycles: usize,
+    inputs: &'a [KeyEvent],
+}
+
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "differential", name]
+        .iter()
+        .collect()
+}
+
+fn run(case: &Case) -> Vec<TraceRecord> {
+    let rom = assemble_file(&fixture(case.rom)).unwrap_or_else(|err| panic!("{}", err));
+
+    let mut chip8 = Chip8::headless(case.quirks);
+    chip8.load_rom(&rom).unwrap();
+    let recording = RecordingTrace::new();
+    chip8.set_tracer(Box::new(recording.clone()));
+
+    for cycle in 0..case.cycles {
+        for event in case.inputs.iter().filter(|event| event.cycle == cycle) {
+            chip8.keyboard_mut().set_key(event.key, event.pressed);
+        }
+        if let Err(err) = chip8.step() {
+            panic!("{}: cycle {} faulted: {}", case.golden, cycle, err);
+        }
+        // Timers stay still, but each cycle's presses and releases are only seen by it
+        chip8.keyboard_mut().end_frame();
+    }
+
+    recording.records()
+}
+
+fn fields(record: &TraceRecord) -> Vec<(String, String)> {
+    let mut fields = vec![
+        ("PC".to_string(), format!("{:04X}", record.pc)),
+        ("opcode".to_string(), format!("{:04X}", record.opcode)),
+    ];
+    for (reg, value) in record.registers.iter().enumerate() {
+        fields.push((format!("V{:X}", reg), format!("{:02X}", value)));
+    }
+    fields.push(("I".to_string(), format!("{:04X}", record.address_register)));
+    fields.push(("stack depth".to_string(), record.stack_depth.to_string()));
+    fields.push(("DT".to_string(), format!("{:02X}", record.delay_timer)));
+    fields.push(("ST".to_string(), format!("{:02X}", record.sound_timer)));
+    fields
+}
+
+/// Describes the first cycle where the traces disagree, if they do.
+fn first_divergence(expected: &[TraceRecord], actual: &[TraceRecord]) -> Option<String> {
+    for (cycle, (want, got)) in expected.iter().zip(actual).enumerate() {
+        let difference = fields(want)
+            .into_iter()
+            .zip(fields(got))
+            .find(|(want, got)| want.1 != got.1);
+        if let Some(((field, want_value), (_, got_value))) = difference {
+            return Some(format!(
+                "cycle {}: {} is {}, expected {}\n  expected: {}\n  actual:   {}",
+                cycle, field, got_value, want_value, want, got
+            ));
+        }
+    }
+
+    if expected.len() != actual.len() {
+        return Some(format!(
+            "ran {} instructions, expected {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    None
+}
+
+fn check(case: Case) {
+    let actual = run(&case);
+    let golden = fixture(case.golden);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, trace::write_trace(&actual, TraceFormat::Text)).unwrap();
+        return;
+    }
+
+    let data = fs::read(&golden)
+        .unwrap_or_else(|err| panic!("{}: {} (run with UPDATE_GOLDEN=1?)", case.golden, err));
+    let expected = trace::read_trace(&data).unwrap_or_else(|err| panic!("{}", err));
+
+    if let Some(divergence) = first_divergence(&expected, &actual) {
+        panic!("{} diverges at {}", case.golden, divergence);
+    }
+}
+
+#[test]
+fn arithmetic_flags() {
+    check(Case {
+        rom: "arithmetic.asm",
+        golden: "arithmetic.trace",
+        quirks: Quirks::default(),
+        cycles: 200,
+        inputs: &[],
+    });
+}
+
+#[test]
+fn arithmetic_flags_with_vip_quirks() {
+    check(Case {
+        rom: "arithmetic.asm",
+        golden: "arithmetic-vip.trace",
+        quirks: Quirks::cosmac_vip(),
+        cycles: 200,
+        inputs: &[],
+    });
+}
+
+const KEY_SCRIPT: &[KeyEvent] = &[
+    press(5, 0x5),
+    release(20, 0x5),
+    press(30, 0xA),
+    release(32, 0xA),
+    press(50, 0x5),
+    press(60, 0x3),
+    release(61, 0x3),
+    release(70, 0x5),
+    press(90, 0x0),
+];
+
+#[test]
+fn scripted_keypad() {
+    check(Case {
+        rom: "keypad.asm",
+        golden: "keypad.trace",
+        quirks: Quirks::default(),
+        cycles: 120,
+        inputs: KEY_SCRIPT,
+    });
+}
+
+#[test]
+fn scripted_keypad_waiting_for_press() {
+    check(Case {
+        rom: "keypad.asm",
+        golden: "keypad-on-press.trace",
+        quirks: Quirks {
+            wait_key_on_press: true,
+            ..Quirks::default()
+        },
+        cycles: 120,
+        inputs: KEY_SCRIPT,
+    });
+}
@@ -0,0 +1,68 @@
+; Flag handling of the 8XYN arithmetic, logic and shift instructions, plus the
+; registers loaded back from memory. Runs to EXIT without using the timers or RND,
+; so every run gives the same trace.
+
+    LD V0, 0xFF
+    LD V1, 0x01
+    ADD V0, V1          ; carry
+    LD V0, 0x10
+    ADD V0, V1          ; no carry
+    LD VF, 0xF0
+    ADD VF, V1          ; VF as the destination ends up holding the flag
+    LD VF, 0xFF
+    ADD VF, V1
+
+    LD V2, 0x05
+    LD V3, 0x07
+    SUB V2, V3          ; borrow
+    LD V2, 0x07
+    SUB V2, V3          ; equal, so no borrow
+    LD VF, 0x03
+    SUB VF, V1
+
+    LD V4, 0x05
+    SUBN V4, V3         ; no borrow
+    LD V4, 0x09
+    SUBN V4, V3         ; borrow
+    LD VF, 0x01
+    SUBN VF, V3
+
+    LD V5, 0x81
+    LD V6, 0x42
+    SHR V5, V6          ; shifts V6 with the shift_uses_vy quirk
+    LD V5, 0x81
+    SHL V5, V6
+    SHR V6
+    SHL V6
+    LD VF, 0x81
+    SHR VF
+    LD VF, 0x81
+    SHL VF
+
+    LD V7, 0x0F
+    LD V8, 0xF0
+    LD VF, 0x55
+    OR V7, V8           ; clears VF with the vf_reset quirk
+    LD VF, 0x55
+    AND V7, V8
+    LD VF, 0x55
+    XOR V7, V8
+
+    CALL store
+    LD I, numbers
+    LD V9, 0xFF
+    LD B, V9
+    LD V2, [I]          ; moves I on with the load_store_increments_i quirk
+    LD VA, 0x10
+    ADD I, VA
+    EXIT
+
+store:
+    LD I, scratch
+    LD [I], V8
+    RET
+
+numbers:
+    db 0, 0, 0
+scratch:
+    db 0, 0, 0, 0, 0, 0, 0, 0, 0
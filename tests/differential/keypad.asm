@@ -0,0 +1,18 @@
+; Reads the keypad four times, counting how often key 5 is held afterwards.
+; The test scripts the key presses.
+
+loop:
+    LD V0, K            ; waits for a key
+    LD V1, 0x05
+    SKP V1
+    JP released
+    ADD V2, 0x01
+released:
+    SKNP V1
+    JP held
+    ADD V4, 0x01
+held:
+    ADD V3, 0x01
+    SE V3, 0x04
+    JP loop
+    EXIT